@@ -1,7 +1,5 @@
 #![allow(dead_code)]
 
-use clap::{Command, CommandFactory};
-
 #[path = "src/opts.rs"]
 mod opts;
 
@@ -11,20 +9,14 @@ mod utils;
 #[path = "src/config.rs"]
 mod config;
 
-fn main() -> std::io::Result<()> {
-    let out_man_dir = env!("CARGO_MANIFEST_DIR").to_string() + "/man";
-
-    create_man_page("blatex.1", opts::Args::command(), out_man_dir.as_str())?;
-
-    Ok(())
-}
-
-fn create_man_page(filename: &str, cmd: Command, out_dir: &str) -> std::io::Result<()> {
-    let man = clap_mangen::Man::new(cmd);
-    let mut buffer: Vec<u8> = Default::default();
-    man.render(&mut buffer)?;
+#[path = "src/man.rs"]
+mod man;
 
-    std::fs::write(out_dir.to_string() + "/" + filename, buffer)?;
+#[path = "src/meta.rs"]
+mod meta;
 
-    Ok(())
+fn main() {
+    // Man pages are generated into the build directory. Use `blatex man <dir>` to install them.
+    let out_dir = std::env::var("OUT_DIR").expect("cargo should set OUT_DIR");
+    man::write_man_pages(&std::path::Path::new(&out_dir).join("man"));
 }
@@ -0,0 +1,282 @@
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use termion::color::{self, Fg};
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::{
+    deps::{self, DepGraph},
+    exit_with_error, log,
+    opts::{ArchiveArgs, ArchiveFormat, Config},
+    utils,
+};
+
+/// Number of lines of compiler output shown when verifying an archive fails
+const VERIFY_OUTPUT_LINES: usize = 20;
+
+impl ArchiveFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// The path of a file of the document relative to the root, or `None` if it is outside of the root
+fn relative_to_root(root: &Path, path: &Path) -> Option<PathBuf> {
+    deps::normalize(&root.join(path))
+        .strip_prefix(deps::normalize(root))
+        .ok()
+        .map(Path::to_path_buf)
+}
+
+#[test]
+fn test_relative_to_root() {
+    let root = Path::new("/doc");
+    assert_eq!(
+        relative_to_root(root, Path::new("chapters/../intro.tex")),
+        Some(PathBuf::from("intro.tex"))
+    );
+    assert_eq!(
+        relative_to_root(root, Path::new("/doc/plot.png")),
+        Some(PathBuf::from("plot.png"))
+    );
+    assert_eq!(
+        relative_to_root(root, Path::new("../shared/macros.tex")),
+        None
+    );
+    assert_eq!(
+        relative_to_root(root, Path::new("/usr/share/macros.tex")),
+        None
+    );
+}
+
+/// The files to put in the archive, as pairs of the path within the archive and the path of the
+/// file. Files which are included by the document but do not exist are returned separately. Files
+/// outside of the document root cannot be put in the archive without changing the sources
+/// referring to them, so they are an error.
+pub fn archive_files(
+    config: &Config,
+    main_file: &Path,
+    recorder: bool,
+) -> (Vec<(PathBuf, PathBuf)>, Vec<PathBuf>) {
+    let fls_file = log::aux_file(config, main_file, "fls");
+    let graph = DepGraph::build(
+        &config.root,
+        main_file,
+        recorder.then_some(fls_file.as_path()),
+    );
+    let (existing, missing): (Vec<PathBuf>, Vec<PathBuf>) = graph
+        .files()
+        .into_iter()
+        .map(|d| d.path)
+        .partition(|p| graph.exists(p));
+
+    let mut outside = vec![];
+    let mut files: Vec<(PathBuf, PathBuf)> = vec![];
+    for path in existing {
+        match relative_to_root(&config.root, &path) {
+            Some(name) => files.push((name, config.root.join(path))),
+            None => outside.push(format!("`{}`", path.display())),
+        }
+    }
+    if !outside.is_empty() {
+        exit_with_error!(
+            "Cannot archive files outside of the document root: {}. Move them into `{}`.",
+            outside.join(", "),
+            config.root.display()
+        );
+    }
+
+    // Publishers often want the generated bibliography instead of running bibtex themselves. It
+    // is put next to the main file, where the compiler looks for it.
+    let bbl = log::find_log_file(config, main_file).with_extension("bbl");
+    let bbl_name = graph.main_file.with_extension("bbl");
+    if bbl.is_file() && !files.iter().any(|(name, _)| name == &bbl_name) {
+        files.push((bbl_name, bbl));
+    }
+
+    (files, missing)
+}
+
+/// The main file given as an argument, or the configured one. Exits if it does not exist.
+pub fn main_file(config: &Config, arg: Option<String>) -> PathBuf {
+    let main_file = match arg {
+        Some(f) => PathBuf::from(f),
+        None => config.main_file.clone(),
+    };
+    if !config.root.join(&main_file).is_file() {
+        exit_with_error!("Cannot find main file `{}`.", main_file.display());
+    }
+    main_file
+}
+
+/// Warn about files which are included by the document but do not exist
+pub fn warn_missing(missing: &[PathBuf]) {
+    for m in missing {
+        eprintln!(
+            "{}WARNING: `{}` is included by the document but does not exist.{}",
+            Fg(color::Yellow),
+            m.display(),
+            Fg(color::Reset)
+        );
+    }
+}
+
+/// Tell the user how many files were written to the archive at `output`
+pub fn print_written(config: &Config, count: usize, output: &Path) {
+    println!(
+        "Wrote {} files to `{}`.",
+        count,
+        output
+            .strip_prefix(&config.root)
+            .unwrap_or(output)
+            .display()
+    );
+}
+
+fn write_zip(output: &Path, files: &[(PathBuf, PathBuf)]) -> zip::result::ZipResult<()> {
+    let mut zip = ZipWriter::new(File::create(output)?);
+    for (name, path) in files {
+        zip.start_file(name.to_string_lossy(), FileOptions::default())?;
+        io::copy(&mut File::open(path)?, &mut zip)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn write_tar_gz(output: &Path, files: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    let encoder = GzEncoder::new(File::create(output)?, Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    for (name, path) in files {
+        tar.append_path_with_name(path, name)?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn extract(archive: &Path, format: ArchiveFormat, dir: &Path) {
+    let result = match format {
+        ArchiveFormat::Zip => {
+            zip_extensions::zip_extract(&archive.to_path_buf(), &dir.to_path_buf())
+                .map_err(|e| e.to_string())
+        }
+        ArchiveFormat::TarGz => File::open(archive)
+            .and_then(|f| tar::Archive::new(GzDecoder::new(f)).unpack(dir))
+            .map_err(|e| e.to_string()),
+    };
+    if let Err(e) = result {
+        exit_with_error!("Could not extract `{}`: {}", archive.display(), e);
+    }
+}
+
+/// Compile the document from the archive in a temporary directory, to check that no files are
+/// missing from it
+fn verify(config: &Config, main_file: &Path, archive: &Path, format: ArchiveFormat) {
+    let dir = config.temp_dir.join("archive-check");
+    if dir.exists() {
+        utils::remove_dir_all(&dir);
+    }
+    utils::create_dir_all(&dir);
+    extract(archive, format, &dir);
+
+    let cmd = utils::replace_path_placeholders(&config.compile_cmd, main_file, &config.root);
+    println!(
+        "{}Compiling the archive with `{}`.{}",
+        Fg(color::Blue),
+        cmd,
+        Fg(color::Reset)
+    );
+
+    let output = match Command::new("sh")
+        .arg("-c")
+        .arg(&cmd)
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(o) => o,
+        Err(e) => exit_with_error!("Could not run compile command: {}", e),
+    };
+
+    let archive_config = Config {
+        root: dir.clone(),
+        ..config.clone()
+    };
+    let pdf = log::find_pdf_file(&archive_config, main_file);
+
+    if !output.status.success() || !pdf.is_file() {
+        let text = String::from_utf8_lossy(&output.stdout).to_string()
+            + &String::from_utf8_lossy(&output.stderr);
+        let lines: Vec<&str> = text.lines().collect();
+        for line in &lines[lines.len().saturating_sub(VERIFY_OUTPUT_LINES)..] {
+            println!("{line}");
+        }
+        exit_with_error!(
+            "\n{}The archive does not compile on its own. The files are kept in `{}`.{}",
+            Fg(color::Red),
+            dir.display(),
+            Fg(color::Reset)
+        );
+    }
+
+    utils::remove_dir_all(&dir);
+    println!(
+        "{}The archive compiles.{}",
+        Fg(color::Green),
+        Fg(color::Reset)
+    );
+}
+
+pub fn archive(config: Config, args: ArchiveArgs) {
+    let main_file = main_file(&config, args.main_file);
+
+    let output = args.output.map(PathBuf::from);
+    let format = args
+        .format
+        .or_else(|| output.as_deref().and_then(ArchiveFormat::from_path))
+        .unwrap_or(ArchiveFormat::Zip);
+    let output = match output {
+        Some(o) => config.root.join(o),
+        None => {
+            let stem = main_file.with_extension("");
+            config
+                .root
+                .join(format!("{}.{}", stem.display(), format.extension()))
+        }
+    };
+
+    let (files, missing) = archive_files(&config, &main_file, args.recorder);
+    warn_missing(&missing);
+
+    let result = match format {
+        ArchiveFormat::Zip => write_zip(&output, &files).map_err(|e| e.to_string()),
+        ArchiveFormat::TarGz => write_tar_gz(&output, &files).map_err(|e| e.to_string()),
+    };
+    if let Err(e) = result {
+        exit_with_error!("Could not write archive `{}`: {}", output.display(), e);
+    }
+
+    print_written(&config, files.len(), &output);
+
+    if args.verify {
+        verify(&config, &main_file, &output, format);
+    }
+}
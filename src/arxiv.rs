@@ -0,0 +1,378 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use termion::color::{self, Fg};
+
+use crate::{
+    archive,
+    deps::{self, find_commands},
+    exit_with_error,
+    opts::{ArxivArgs, Config},
+    utils,
+};
+
+/// Commands whose argument refers to a file in the document
+const FILE_COMMANDS: &[&str] = &[
+    "input",
+    "include",
+    "subfile",
+    "includegraphics",
+    "bibliography",
+    "addbibresource",
+];
+
+/// Replace characters arXiv does not accept in file names
+fn sanitize(path: &Path) -> PathBuf {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_+-.,=/".contains(c);
+    PathBuf::from(
+        path.to_string_lossy()
+            .chars()
+            .map(|c| if safe(c) { c } else { '_' })
+            .collect::<String>(),
+    )
+}
+
+#[test]
+fn test_sanitize() {
+    assert_eq!(
+        sanitize(Path::new("figures/my plot (1).png")),
+        PathBuf::from("figures/my_plot__1_.png")
+    );
+    assert_eq!(
+        sanitize(Path::new("chapters/intro.tex")),
+        PathBuf::from("chapters/intro.tex")
+    );
+}
+
+/// New names of the files in the archive. With `flatten`, every file is moved to the top
+/// directory, prefixing the name with its directories if two files have the same name, and
+/// numbering it if that name is taken as well.
+fn new_names(files: &[PathBuf], flatten: bool) -> Vec<PathBuf> {
+    let mut taken = HashSet::new();
+    files
+        .iter()
+        .map(|f| {
+            let name = sanitize(f);
+            if !flatten {
+                return name;
+            }
+            let flat = PathBuf::from(name.file_name().unwrap_or(name.as_os_str()));
+            let prefixed = PathBuf::from(name.to_string_lossy().replace('/', "_"));
+            let numbered = (2..).map(|n| {
+                let stem = prefixed.file_stem().unwrap_or_default().to_string_lossy();
+                match prefixed.extension() {
+                    Some(ext) => PathBuf::from(format!("{stem}-{n}.{}", ext.to_string_lossy())),
+                    None => PathBuf::from(format!("{stem}-{n}")),
+                }
+            });
+            let new = [flat, prefixed.clone()]
+                .into_iter()
+                .chain(numbered)
+                .find(|n| !taken.contains(n))
+                .unwrap();
+            taken.insert(new.clone());
+            new
+        })
+        .collect()
+}
+
+#[test]
+fn test_new_names() {
+    let files = vec![
+        PathBuf::from("a/fig.png"),
+        PathBuf::from("b/a/fig.png"),
+        PathBuf::from("b_a_fig.png"),
+        PathBuf::from("c/b_a_fig.png"),
+    ];
+    assert_eq!(
+        new_names(&files, true),
+        vec![
+            PathBuf::from("fig.png"),
+            PathBuf::from("b_a_fig.png"),
+            PathBuf::from("b_a_fig-2.png"),
+            PathBuf::from("c_b_a_fig.png"),
+        ]
+    );
+    assert_eq!(new_names(&files, false), files);
+}
+
+/// Find the file an argument like `chapters/intro` or `plot` refers to. A file at that path from
+/// the root is preferred, otherwise the argument may be the end of the path, as with
+/// `\graphicspath`. Files differing only in their extension count as one match.
+fn resolve(arg: &str, files: &[PathBuf]) -> Result<Option<usize>, String> {
+    let arg = deps::normalize(Path::new(arg.trim()));
+    if let Some(i) = files
+        .iter()
+        .position(|f| *f == arg || f.with_extension("") == arg)
+    {
+        return Ok(Some(i));
+    }
+
+    let matches: Vec<usize> = (0..files.len())
+        .filter(|&i| files[i].ends_with(&arg) || files[i].with_extension("").ends_with(&arg))
+        .collect();
+    let stems: HashSet<PathBuf> = matches
+        .iter()
+        .map(|&i| files[i].with_extension(""))
+        .collect();
+    match stems.len() {
+        0 | 1 => Ok(matches.first().copied()),
+        _ => Err(format!(
+            "`{}` could refer to any of {}.",
+            arg.display(),
+            matches
+                .iter()
+                .map(|&i| format!("`{}`", files[i].display()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[test]
+fn test_resolve() {
+    let files = vec![
+        PathBuf::from("main.tex"),
+        PathBuf::from("a/fig.png"),
+        PathBuf::from("b/fig.png"),
+        PathBuf::from("b/plot.pdf"),
+        PathBuf::from("b/plot.png"),
+    ];
+    assert_eq!(resolve("main", &files), Ok(Some(0)));
+    assert_eq!(resolve("b/fig.png", &files), Ok(Some(2)));
+    assert_eq!(resolve("plot", &files), Ok(Some(3)));
+    assert_eq!(resolve("missing", &files), Ok(None));
+    assert_eq!(
+        resolve("fig", &files),
+        Err("`fig` could refer to any of `a/fig.png`, `b/fig.png`.".to_string())
+    );
+}
+
+/// Point the file arguments of commands in `source` to the renamed files
+fn rewrite_references(
+    source: &str,
+    files: &[PathBuf],
+    names: &[PathBuf],
+) -> Result<String, String> {
+    let mut out = source.to_string();
+    if files == names {
+        return Ok(out);
+    }
+    for cmd in find_commands(source, FILE_COMMANDS).iter().rev() {
+        let mut args = vec![];
+        for arg in cmd.arg.split(',') {
+            args.push(match resolve(arg, files)? {
+                Some(i) if files[i] != names[i] => {
+                    let name = match Path::new(arg.trim()).extension() {
+                        Some(_) => names[i].clone(),
+                        None => names[i].with_extension(""),
+                    };
+                    name.display().to_string()
+                }
+                _ => arg.to_string(),
+            });
+        }
+
+        // The argument ends right before the closing brace of the command
+        let arg_end = cmd.end - 1;
+        out.replace_range(arg_end - cmd.arg.len()..arg_end, &args.join(","));
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_rewrite_references() {
+    let files = vec![
+        PathBuf::from("main.tex"),
+        PathBuf::from("chapters/intro.tex"),
+        PathBuf::from("figures/my plot.png"),
+    ];
+    let names = new_names(&files, true);
+    assert_eq!(
+        names,
+        vec![
+            PathBuf::from("main.tex"),
+            PathBuf::from("intro.tex"),
+            PathBuf::from("my_plot.png")
+        ]
+    );
+    assert_eq!(
+        rewrite_references(
+            r"\include{chapters/intro} \includegraphics[width=2cm]{my plot}",
+            &files,
+            &names
+        ),
+        Ok(r"\include{intro} \includegraphics[width=2cm]{my_plot}".to_string())
+    );
+}
+
+/// Replace `\bibliography` with the contents of the generated bibliography
+fn inline_bbl(source: &str, bbl: &str) -> Option<String> {
+    let cmd = find_commands(source, &["bibliography"])
+        .into_iter()
+        .next()?;
+    let mut out = source.to_string();
+    out.replace_range(cmd.start..cmd.end, bbl.trim_end());
+    Some(out)
+}
+
+/// Warn about settings arXiv does not support
+fn check_compatibility(config: &Config, sources: &[&String]) {
+    let mut warnings = vec![];
+    for word in utils::split_command(&config.compile_cmd) {
+        let option = word.trim_start_matches('-');
+        let program = Path::new(&word)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if ["shell-escape", "enable-write18"].contains(&option) {
+            warnings.push(format!(
+                "`compile_cmd` uses `{word}`, but arXiv compiles without shell escape."
+            ));
+        } else if ["xelatex", "lualatex", "pdfxe", "pdflua"].contains(&option)
+            || ["xelatex", "lualatex"].contains(&program.as_str())
+        {
+            warnings.push(format!(
+                "`compile_cmd` uses `{word}`, but arXiv compiles with pdflatex."
+            ));
+        }
+    }
+
+    let uses_minted = sources.iter().any(|s| {
+        find_commands(s, &["usepackage"])
+            .iter()
+            .any(|c| c.arg.split(',').any(|p| p.trim() == "minted"))
+    });
+    if uses_minted {
+        warnings.push(
+            "The `minted` package needs shell escape, which arXiv does not allow.".to_string(),
+        );
+    }
+
+    for w in warnings {
+        eprintln!("{}WARNING: {}{}", Fg(color::Yellow), w, Fg(color::Reset));
+    }
+}
+
+fn append(tar: &mut tar::Builder<GzEncoder<File>>, name: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, name, contents)
+}
+
+/// A file to put in the submission
+struct SubmissionFile {
+    /// Path relative to the document root
+    name: PathBuf,
+    path: PathBuf,
+
+    /// Contents of TeX files, without comments
+    source: Option<String>,
+}
+
+impl SubmissionFile {
+    fn has_extension(&self, extensions: &[&str]) -> bool {
+        self.name
+            .extension()
+            .is_some_and(|e| extensions.iter().any(|x| e == *x))
+    }
+}
+
+pub fn arxiv(config: Config, args: ArxivArgs) {
+    let main_file = archive::main_file(&config, args.main_file);
+    let (files, missing) = archive::archive_files(&config, &main_file, false);
+    archive::warn_missing(&missing);
+
+    let read = |path: &Path| match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => exit_with_error!("Could not read `{}`: {}", path.display(), e),
+    };
+    let mut files: Vec<SubmissionFile> = files
+        .into_iter()
+        .map(|(name, path)| {
+            let source = match name.extension().is_some_and(|e| e == "tex") {
+                true => Some(deps::strip_comments(&read(&path), false)),
+                false => None,
+            };
+            SubmissionFile { name, path, source }
+        })
+        .collect();
+
+    let sources: Vec<&String> = files.iter().filter_map(|f| f.source.as_ref()).collect();
+    check_compatibility(&config, &sources);
+
+    // arXiv only runs bibtex if there is no bibliography, so the generated one is inlined and the
+    // databases are left out
+    if let Some(bbl) = files.iter().position(|f| f.has_extension(&["bbl"])) {
+        let contents = read(&files[bbl].path);
+        let bbl_name = files[bbl].name.clone();
+        let inlined = files.iter_mut().any(|f| {
+            match f.source.as_deref().and_then(|s| inline_bbl(s, &contents)) {
+                Some(new) => {
+                    f.source = Some(new);
+                    true
+                }
+                None => false,
+            }
+        });
+        if inlined {
+            println!("Inlined the bibliography from `{}`.", bbl_name.display());
+            files.retain(|f| !f.has_extension(&["bbl", "bib"]));
+        }
+    }
+
+    let old: Vec<PathBuf> = files.iter().map(|f| f.name.clone()).collect();
+    let names = new_names(&old, args.flatten);
+    for (o, n) in old.iter().zip(&names) {
+        if o != n {
+            println!("Renamed `{}` to `{}`.", o.display(), n.display());
+        }
+    }
+
+    let output = match args.output {
+        Some(o) => config.root.join(o),
+        None => {
+            let stem = main_file.with_extension("");
+            config.root.join(format!("{}-arxiv.tar.gz", stem.display()))
+        }
+    };
+
+    let sources: Vec<Option<String>> = files
+        .iter()
+        .map(|f| {
+            f.source.as_ref().map(|s| {
+                rewrite_references(s, &old, &names).unwrap_or_else(|e| {
+                    exit_with_error!(
+                        "Could not update the references in `{}`: {}",
+                        f.name.display(),
+                        e
+                    )
+                })
+            })
+        })
+        .collect();
+
+    let result = File::create(&output).and_then(|f| {
+        let mut tar = tar::Builder::new(GzEncoder::new(f, Compression::default()));
+        for ((file, source), name) in files.iter().zip(&sources).zip(&names) {
+            match source {
+                Some(s) => append(&mut tar, name, s.as_bytes())?,
+                None => tar.append_path_with_name(&file.path, name)?,
+            }
+        }
+        tar.into_inner()?.finish()?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        exit_with_error!("Could not write archive `{}`: {}", output.display(), e);
+    }
+
+    archive::print_written(&config, files.len(), &output);
+}
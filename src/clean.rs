@@ -14,7 +14,8 @@ pub fn clean(config: Config, args: CleanArgs) {
         None => config.main_file,
     };
 
-    let cmd = utils::replace_path_placeholders(&config.clean_cmd, main_file.as_path());
+    let cmd =
+        utils::replace_path_placeholders(&config.clean_cmd, main_file.as_path(), &config.root);
     let prefix = format!("cd \"{}\"", config.root.display());
 
     let cmd = prefix + " && " + cmd.as_str();
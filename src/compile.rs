@@ -1,46 +1,369 @@
-use std::path::PathBuf;
+use std::{
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
 
 use termion::color::{self, Fg};
 
 use crate::{
     exit_with_error, log,
+    manifest::BuildManifest,
+    meta,
     opts::{CompileArgs, Config},
-    utils,
+    packages,
+    stats::{self, Timings},
+    utils, viewer,
 };
 
 pub fn compile(config: Config, args: CompileArgs) {
-    let main_file = match args.main_file {
+    let main_file = match &args.main_file {
         Some(f) => PathBuf::from(f),
         None => config.main_file.clone(),
     };
-    compile_file(config, main_file);
+    compile_file(config, main_file, &args);
 }
 
-pub fn compile_file(config: Config, main_file: PathBuf) {
-    let cmd = utils::replace_path_placeholders(&config.compile_cmd, main_file.as_path());
-    let prefix = format!("cd \"{}\"", config.root.display());
+/// Progress of a running compilation, read from the output of the compiler
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Progress {
+    /// Number of compiler runs started by latexmk
+    pass: usize,
 
-    let cmd = prefix + " && " + cmd.as_str();
+    /// Last page written to the output
+    page: usize,
+}
+
+impl Progress {
+    /// Update the progress with new output from the compiler. TeX writes `[<page>` when it ships
+    /// out a page and latexmk writes `Run number <n>` when it starts a pass.
+    fn update(&mut self, output: &str) {
+        for line in output.lines() {
+            if let Some((_, rest)) = line.split_once("Run number ") {
+                if let Some(n) = rest.split_whitespace().next().and_then(|n| n.parse().ok()) {
+                    self.pass = n;
+                    self.page = 0;
+                }
+            }
+        }
+
+        for (i, _) in output.match_indices('[') {
+            let digits: String = output[i + 1..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            if let Ok(page) = digits.parse() {
+                self.page = page;
+            }
+        }
+    }
+
+    fn print(&self) {
+        let pass = match self.pass {
+            0 => String::new(),
+            n => format!("pass {n}, "),
+        };
+        eprint!("\r\x1b[KCompiling: {}page {}", pass, self.page);
+        let _ = io::stderr().flush();
+    }
+}
+
+#[test]
+fn test_progress() {
+    let mut progress = Progress::default();
+    progress.update(
+        "Run number 1 of rule 'pdflatex'\n(./main.tex [1{/usr/share/texmf/pdftex.map}] [2]",
+    );
+    assert_eq!(progress, Progress { pass: 1, page: 2 });
+    progress.update(" [3] (./chapters/intro.tex [4]");
+    assert_eq!(progress, Progress { pass: 1, page: 4 });
+    progress.update("Run number 2 of rule 'pdflatex'\n");
+    assert_eq!(progress, Progress { pass: 2, page: 0 });
+}
+
+fn spawn_error(e: io::Error) -> ! {
+    exit_with_error!("Could not run compilation command: {}", e)
+}
+
+/// Output that ends with one of these means the compiler is waiting for input. TeX's bare `*`
+/// prompt is left out, as it cannot be told apart from the many log lines starting with `*`.
+const INPUT_PROMPTS: &[&str] = &[
+    "\n? ",
+    "Enter file name: ",
+    "Please type another input file name: ",
+];
+
+/// How long the compiler may be silent after printing a prompt before it is considered stuck
+const PROMPT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// How a compilation ended
+enum Outcome {
+    Exited(ExitStatus),
+    TimedOut(Duration),
+    WaitingForInput,
+}
+
+fn is_waiting_for_input(output: &[u8]) -> bool {
+    let tail = String::from_utf8_lossy(&output[output.len().saturating_sub(64)..]);
+    INPUT_PROMPTS.iter().any(|p| tail.ends_with(p))
+}
+
+#[test]
+fn test_is_waiting_for_input() {
+    assert!(is_waiting_for_input(
+        b"! Undefined control sequence.\nl.3 \\foo\n? "
+    ));
+    assert!(is_waiting_for_input(
+        b"! I can't find file `x'.\nPlease type another input file name: "
+    ));
+    assert!(!is_waiting_for_input(
+        b"(./main.tex\n*geometry* driver: auto-detecting\n*"
+    ));
+    assert!(!is_waiting_for_input(b"[1] [2]"));
+}
+
+/// Kill a process and every process it started
+fn kill_process_tree(child: &mut Child) {
+    if cfg!(unix) {
+        let _ = Command::new("kill")
+            .args(["-KILL", "--", &format!("-{}", child.id())])
+            .status();
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Run the compilation command. In quiet mode, the output is captured and a progress indicator
+/// is shown instead, if stderr is a terminal. The compiler is stopped if it runs for longer than
+/// `timeout` or waits for input.
+fn run(
+    cmd: &str,
+    quiet: bool,
+    timeout: Option<Duration>,
+    timings: &mut Timings,
+) -> (Outcome, Vec<u8>) {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(cmd)
+        // Kept open without writing to it, such that a prompt blocks instead of reading EOF
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(match quiet {
+            true => Stdio::piped(),
+            false => Stdio::inherit(),
+        });
+    utils::new_process_group(&mut command);
+
+    let mut child = match command.spawn() {
+        Ok(c) => c,
+        Err(e) => spawn_error(e),
+    };
+
+    let stderr_reader = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut output = vec![];
+            let _ = stderr.read_to_end(&mut output);
+            output
+        })
+    });
+
+    let (sender, receiver) = mpsc::channel();
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        while let Ok(n @ 1..) = stdout.read(&mut buffer) {
+            if sender.send(buffer[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let show_progress = quiet && termion::is_tty(&io::stderr());
+    let mut progress = Progress::default();
+    let mut output = vec![];
+    let start = Instant::now();
+    let mut last_output = Instant::now();
 
-    println!(
-        "{}Running command: `{}`{}\n",
-        Fg(color::Blue),
-        cmd,
+    let outcome = loop {
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(chunk) => {
+                last_output = Instant::now();
+                timings.update(&String::from_utf8_lossy(&chunk));
+                if show_progress {
+                    progress.update(&String::from_utf8_lossy(&chunk));
+                    progress.print();
+                } else if !quiet {
+                    let mut out = io::stdout();
+                    let _ = out.write_all(&chunk);
+                    let _ = out.flush();
+                }
+                output.extend(chunk);
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => match child.try_wait() {
+                Ok(Some(status)) => break Outcome::Exited(status),
+                Ok(None) => thread::sleep(Duration::from_millis(100)),
+                Err(e) => spawn_error(e),
+            },
+        }
+
+        if let Some(timeout) = timeout.filter(|t| start.elapsed() > *t) {
+            kill_process_tree(&mut child);
+            break Outcome::TimedOut(timeout);
+        }
+        if is_waiting_for_input(&output) && last_output.elapsed() > PROMPT_GRACE_PERIOD {
+            kill_process_tree(&mut child);
+            break Outcome::WaitingForInput;
+        }
+    };
+
+    timings.finish();
+    if show_progress {
+        eprint!("\r\x1b[K");
+    }
+    if let Some(reader) = stderr_reader {
+        output.extend(reader.join().unwrap_or_default());
+    }
+
+    (outcome, output)
+}
+
+/// Exit with an error that shows the last lines the compiler wrote to the log
+fn exit_with_stopped_compiler(config: &Config, main_file: &Path, reason: &str) -> ! {
+    let log_file = log::find_log_file(config, main_file);
+    if log_file.is_file() {
+        let lines = log::read_log_lines(&log_file);
+        let last: Vec<&String> = lines
+            .iter()
+            .rev()
+            .filter(|l| !l.trim().is_empty())
+            .take(3)
+            .collect();
+        println!(
+            "\n{}Last lines of `{}`:{}",
+            Fg(color::Red),
+            log_file
+                .strip_prefix(&config.root)
+                .unwrap_or(&log_file)
+                .display(),
+            Fg(color::Reset)
+        );
+        for line in last.into_iter().rev() {
+            println!("  {}", line);
+        }
+    }
+    exit_with_error!(
+        "\n{}Compilation stopped: {}{}",
+        Fg(color::Red),
+        reason,
         Fg(color::Reset)
     );
+}
+
+pub fn compile_file(config: Config, main_file: PathBuf, args: &CompileArgs) {
+    let build_dir = log::aux_dir(&config, &main_file);
+    meta::write_meta_file(&config, &main_file, &build_dir);
+
+    let cmd =
+        utils::replace_path_placeholders(&config.compile_cmd, main_file.as_path(), &config.root);
+    let mut prefix = format!("cd \"{}\"", config.root.display());
+
+    // The default metadata file is in the build directory, which TeX does not search
+    if config.meta_file.is_none() && build_dir != config.root {
+        prefix += &format!(
+            " && export TEXINPUTS=\"{}:$TEXINPUTS\"",
+            build_dir.display()
+        );
+    }
+
+    let cmd = prefix + " && " + cmd.as_str();
 
-    let status = if cfg!(target_os = "windows") {
+    if !args.force {
+        if let Some(manifest) = BuildManifest::read(&config, &main_file) {
+            if manifest.is_up_to_date(&config, &main_file, &cmd) {
+                println!("`{}` is up to date.", main_file.display());
+                log::check_denied(&config, &main_file, &args.deny);
+                if args.open {
+                    viewer::open(&config, &main_file, false);
+                }
+                return;
+            }
+
+            if args.verbose {
+                let current = BuildManifest::compute(&config, &main_file, &cmd);
+                for path in manifest.changed_inputs(&current) {
+                    println!("Changed since last build: `{}`", path.display());
+                }
+            }
+        }
+    }
+
+    if !args.quiet {
+        println!(
+            "{}Running command: `{}`{}\n",
+            Fg(color::Blue),
+            cmd,
+            Fg(color::Reset)
+        );
+    }
+
+    let timeout = args
+        .timeout
+        .or(config.compile_timeout)
+        .map(Duration::from_secs);
+
+    let mut timings = Timings::start(&config.compile_cmd);
+    let (outcome, output) = if cfg!(target_os = "windows") {
         exit_with_error!("Compilation on windows is currently not supported.");
     } else {
-        match std::process::Command::new("sh").arg("-c").arg(cmd).status() {
-            Ok(s) => s,
-            Err(e) => exit_with_error!("Could not run compilation command: {}", e),
-        }
+        run(&cmd, args.quiet, timeout, &mut timings)
     };
 
+    let status = match outcome {
+        Outcome::Exited(status) => status,
+        Outcome::TimedOut(t) => exit_with_stopped_compiler(
+            &config,
+            &main_file,
+            &match t.as_secs() {
+                1 => "the compiler ran for more than 1 second".to_string(),
+                secs => format!("the compiler ran for more than {secs} seconds"),
+            },
+        ),
+        Outcome::WaitingForInput => exit_with_stopped_compiler(
+            &config,
+            &main_file,
+            "the compiler is waiting for input. Run it with `-interaction=nonstopmode` to avoid this.",
+        ),
+    };
+
+    let filter = log::LogFilter::new(&config, &args.filter);
+    let log_file = log::find_log_file(&config, &main_file);
+
     match status.code() {
         Some(code) => {
             if code != 0 {
+                if packages::check_missing_packages(&config, &main_file, args.install) {
+                    // Compile again now that the packages are installed
+                    let args = CompileArgs {
+                        force: true,
+                        install: false,
+                        ..args.clone()
+                    };
+                    return compile_file(config, main_file, &args);
+                }
+
+                // The compiler output was hidden, so show what went wrong
+                if args.quiet {
+                    match log_file.is_file() {
+                        true => log::print_log(&config, &log_file, &main_file, None, &filter),
+                        false => print!("{}", String::from_utf8_lossy(&output)),
+                    }
+                }
+
                 exit_with_error!(
                     "\n{}Compilation process exited with non-zero exit code: {}{}",
                     Fg(color::Red),
@@ -54,6 +377,29 @@ pub fn compile_file(config: Config, main_file: PathBuf) {
         }
     };
 
+    BuildManifest::compute(&config, &main_file, &cmd).write(&config, &main_file);
+
     // Parse log file
-    log::print_log(config.root, &main_file);
+    if args.verbose {
+        println!(
+            "\n{}Reading log file `{}`.{}",
+            Fg(color::LightBlack),
+            log_file
+                .strip_prefix(&config.root)
+                .unwrap_or(&log_file)
+                .display(),
+            Fg(color::Reset)
+        );
+    }
+    log::print_log(&config, &log_file, &main_file, None, &filter);
+
+    if args.timings {
+        stats::report(&config, &main_file, &timings);
+    }
+
+    log::check_denied(&config, &main_file, &args.deny);
+
+    if args.open {
+        viewer::open(&config, &main_file, false);
+    }
 }
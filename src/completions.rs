@@ -0,0 +1,118 @@
+use clap::CommandFactory;
+use clap_complete::shells;
+
+use crate::opts::{Args, CompletionsArgs, Shell};
+
+/// Command printing the names of the templates, used to complete `init --template`
+const LIST_TEMPLATES: &str = "blatex template list --names 2>/dev/null";
+
+/// Completes template names after `init -t`, and falls back to the generated completions. It is
+/// registered in place of the generated function by `completion_script`.
+fn bash_templates() -> String {
+    format!(
+        r#"
+_blatex_templates() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    if [[ " ${{COMP_WORDS[*]}} " == *" init "* && ( "$prev" == "-t" || "$prev" == "--template" ) ]]; then
+        COMPREPLY=($(compgen -W "$({LIST_TEMPLATES})" -- "$cur"))
+        return 0
+    fi
+    _blatex "$@"
+}}
+"#
+    )
+}
+
+fn zsh_templates() -> String {
+    format!(
+        r#"
+_blatex_templates() {{
+    local -a templates
+    templates=(${{(f)"$({LIST_TEMPLATES})"}})
+    compadd -a templates
+}}
+"#
+    )
+}
+
+fn fish_templates() -> String {
+    format!(
+        "\ncomplete -c blatex -n \"__fish_seen_subcommand_from init\" -s t -l template -f -a \"({LIST_TEMPLATES})\"\n"
+    )
+}
+
+/// The completion script for a shell. The scripts generated by clap are extended to complete
+/// template names, which are only known at runtime.
+fn completion_script(shell: Shell) -> String {
+    let mut cmd = Args::command();
+    let mut buffer: Vec<u8> = vec![];
+    match shell {
+        Shell::Bash => clap_complete::generate(shells::Bash, &mut cmd, "blatex", &mut buffer),
+        Shell::Zsh => clap_complete::generate(shells::Zsh, &mut cmd, "blatex", &mut buffer),
+        Shell::Fish => clap_complete::generate(shells::Fish, &mut cmd, "blatex", &mut buffer),
+    }
+    let script = String::from_utf8_lossy(&buffer).to_string();
+
+    match shell {
+        Shell::Bash => {
+            script.replace("complete -F _blatex ", "complete -F _blatex_templates ")
+                + &bash_templates()
+        }
+        Shell::Zsh => {
+            // The helper has to be defined before the script calls `_blatex` at the end
+            let (compdef, rest) = script.split_once('\n').unwrap_or(("", &script));
+            let rest = rest
+                .lines()
+                .map(|l| match l.split_once(":TEMPLATE:") {
+                    Some((spec, action)) => {
+                        let quote = action.rfind('\'').map(|i| &action[i..]).unwrap_or("");
+                        format!("{spec}:TEMPLATE:_blatex_templates{quote}")
+                    }
+                    None => l.to_string(),
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            format!("{compdef}\n{}\n{rest}\n", zsh_templates())
+        }
+        Shell::Fish => script + &fish_templates(),
+    }
+}
+
+#[test]
+fn test_completion_script() {
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+        assert!(completion_script(shell).contains(LIST_TEMPLATES));
+    }
+
+    let zsh = completion_script(Shell::Zsh);
+    assert!(zsh.starts_with("#compdef blatex\n"));
+    assert!(zsh.contains("'--template=[Name of a template to use]:TEMPLATE:_blatex_templates' \\"));
+}
+
+#[test]
+fn test_bash_completion() {
+    // Complete `blatex comp` with the function the script registers for `blatex`
+    let script = completion_script(Shell::Bash)
+        + r#"
+function=$(complete -p blatex | sed 's/.* -F \([^ ]*\) .*/\1/')
+echo "$function"
+COMP_WORDS=(blatex comp)
+COMP_CWORD=1
+"$function" blatex comp blatex
+echo "${COMPREPLY[@]}"
+"#;
+    let output = std::process::Command::new("bash")
+        .arg("-c")
+        .arg(script)
+        .output()
+        .unwrap();
+    let output = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines[0], "_blatex_templates");
+    assert!(lines[1].split(' ').any(|w| w == "compile"));
+}
+
+pub fn completions(args: CompletionsArgs) {
+    print!("{}", completion_script(args.shell));
+}
@@ -1,8 +1,15 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use toml_edit::{ImDocument, Item, Value};
 
 use crate::{
     exit_with_error,
-    opts::{Config, ConfigCreateArgs},
+    opts::{Config, ConfigCreateArgs, LogLevel, PartialConfig, REMOTE_TEMPLATES_OPTION},
     utils,
 };
 
@@ -43,20 +50,16 @@ pub fn create(cwd: &Path, global: bool, args: &ConfigCreateArgs, config: &Config
 }
 
 fn create_local_configuration_string(config: &Config) -> String {
-    let desc = "# This is your local configuration for this document.\n# Options here will override global ones.\n";
-
-    let config_string = format!(
-        r#"
-main_file = "{main_file}"
-compile_cmd = "{compile_cmd}"
-clean_cmd = "{clean_cmd}"
-"#,
-        main_file = config.main_file.display(),
-        compile_cmd = config.compile_cmd,
-        clean_cmd = config.clean_cmd
-    );
+    let desc = "# This is your local configuration for this document.\n# Options here will override global ones.\n\n";
+
+    let local = PartialConfig {
+        main_file: Some(config.main_file.clone()),
+        compile_cmd: Some(config.compile_cmd.clone()),
+        clean_cmd: Some(config.clean_cmd.clone()),
+        ..Default::default()
+    };
 
-    format!("{}{}", desc, config_string)
+    format!("{}{}", desc, to_toml_string(&local))
 }
 
 fn create_global_configuration_string(config: &Config) -> String {
@@ -64,17 +67,24 @@ fn create_global_configuration_string(config: &Config) -> String {
 # This is your global configuration file. Options here will always get
 # read, but may be overridden by local config files. If you delete
 # options, they will simply be set to their default value.
+
 "#;
 
-    let config_string = match toml::to_string_pretty(&config) {
-        Ok(s) => s,
-        Err(_) => {
-            eprintln!("WARNING: Could not convert configuration to toml.");
-            "".to_string()
-        }
+    // The root and the location of the global config itself depend on where blatex is run
+    let global = PartialConfig {
+        root: None,
+        config_file: None,
+        ..PartialConfig::from(config)
     };
 
-    format!("{}{}", desc, config_string)
+    format!("{}{}", desc, to_toml_string(&global))
+}
+
+fn to_toml_string(config: &PartialConfig) -> String {
+    match toml::to_string_pretty(config) {
+        Ok(s) => s,
+        Err(e) => exit_with_error!("Could not convert configuration to toml: {}", e),
+    }
 }
 
 pub fn show(config: Config, global: bool) {
@@ -85,3 +95,518 @@ pub fn show(config: Config, global: bool) {
     };
     println!("{}", config_string);
 }
+
+/// Type of value a configuration field expects
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    String,
+    Integer,
+    StringList,
+    RemoteTemplates,
+
+    /// A string with one of the given values
+    Choice(&'static [&'static str]),
+}
+
+impl FieldKind {
+    /// Description of the type for documentation
+    pub fn describe(&self) -> String {
+        match self {
+            FieldKind::String => "string".to_string(),
+            FieldKind::Integer => "integer".to_string(),
+            FieldKind::StringList => "array of strings".to_string(),
+            FieldKind::RemoteTemplates => "table".to_string(),
+            FieldKind::Choice(choices) => format!("one of {}", choices.join(", ")),
+        }
+    }
+}
+
+/// All fields that can appear in a configuration file, with a description of each
+pub const CONFIG_FIELDS: &[(&str, FieldKind, &str)] = &[
+    (
+        "root",
+        FieldKind::String,
+        "The root directory of the document. Defaults to the directory containing the local configuration file.",
+    ),
+    (
+        "main_file",
+        FieldKind::String,
+        "The main entry point for the latex compiler, relative to the root.",
+    ),
+    (
+        "compile_cmd",
+        FieldKind::String,
+        "Command for compiling the document. <main-file> is substituted with `main_file` and <main-stem> with `main_file` without the `.tex` extension. <git-rev> is substituted with the abbreviated commit hash and <git-describe> with the output of `git describe --tags --always --dirty`.",
+    ),
+    (
+        "compile_timeout",
+        FieldKind::Integer,
+        "Number of seconds the compilation command may run before it is stopped.",
+    ),
+    (
+        "viewer",
+        FieldKind::String,
+        "Command for opening the compiled document. <pdf> is substituted with the path to the PDF file, which is otherwise added as the last argument. If unset, the first of zathura, evince, okular and xdg-open which is installed is used.",
+    ),
+    (
+        "clean_cmd",
+        FieldKind::String,
+        "Command for cleaning temporary document files. <main-file> and <main-stem> are substituted like in `compile_cmd`.",
+    ),
+    ("data_dir", FieldKind::String, "Directory for application data."),
+    ("templates_dir", FieldKind::String, "Directory for storing templates."),
+    (
+        "template_dirs",
+        FieldKind::StringList,
+        "Additional directories containing templates. They are searched in order before `templates_dir`.",
+    ),
+    (
+        "config_file",
+        FieldKind::String,
+        "Path of the global configuration file.",
+    ),
+    ("temp_dir", FieldKind::String, "Directory used for temporary files."),
+    (
+        REMOTE_TEMPLATES_OPTION,
+        FieldKind::RemoteTemplates,
+        "Templates in git repositories, which are downloaded when used. See REMOTE TEMPLATES.",
+    ),
+    (
+        "extends",
+        FieldKind::StringList,
+        "Configuration files this configuration is based on. Options in this configuration override the ones in the extended files. Relative paths are relative to the directory of this file.",
+    ),
+    (
+        "log_level",
+        FieldKind::Choice(LogLevel::NAMES),
+        "Least severe kind of diagnostic shown from the log file.",
+    ),
+    (
+        "log_ignore",
+        FieldKind::StringList,
+        "Diagnostics containing any of these texts are hidden.",
+    ),
+    (
+        "log_ignore_packages",
+        FieldKind::StringList,
+        "Diagnostics from these packages are hidden.",
+    ),
+    (
+        "meta_file",
+        FieldKind::String,
+        "File written before each compilation, relative to the root. It defines the macros \\blatexGitHash, \\blatexGitRev, \\blatexGitBranch, \\blatexGitTag, \\blatexGitDescribe, \\blatexBuildDate and \\blatexTarget, and the conditional \\ifblatexGitDirty. Defaults to blatex-meta.tex in the auxiliary or output directory, which is added to TEXINPUTS such that \\input{blatex-meta} finds it. Set to an empty string to not write it. As \\blatexBuildDate changes every day, documents including the file are rebuilt once a day, unless SOURCE_DATE_EPOCH is set to fix the date.",
+    ),
+];
+
+/// Fields that describe where a remote template is located. Remote templates may also set any
+/// configuration field except `remote_templates`.
+const REMOTE_TEMPLATE_FIELDS: &[&str] = &["repo", "path", "branch"];
+
+/// The kind of a TOML value. Nested values remember where they were defined.
+enum ValueKind {
+    String(String),
+    Array(Vec<Located>),
+    Table(BTreeMap<String, Located>),
+    Other(&'static str),
+}
+
+impl ValueKind {
+    fn type_name(&self) -> &'static str {
+        match self {
+            ValueKind::String(_) => "string",
+            ValueKind::Array(_) => "array",
+            ValueKind::Table(_) => "table",
+            ValueKind::Other(name) => name,
+        }
+    }
+}
+
+/// A TOML value and the byte range where it was defined. Implicitly defined tables, like
+/// `remote_templates` in `[remote_templates.report]`, have no location.
+struct Located {
+    span: Option<Range<usize>>,
+    kind: ValueKind,
+}
+
+impl Located {
+    fn span(&self) -> Range<usize> {
+        self.span.clone().unwrap_or(0..0)
+    }
+
+    fn from_table<'a>(table: impl Iterator<Item = (&'a str, &'a Item)>) -> ValueKind {
+        ValueKind::Table(
+            table
+                .map(|(k, v)| (k.to_string(), Located::from_item(v)))
+                .collect(),
+        )
+    }
+
+    fn from_item(item: &Item) -> Self {
+        match item {
+            Item::Value(v) => Located::from_value(v),
+            Item::Table(t) => Located {
+                span: t.span(),
+                kind: Located::from_table(t.iter()),
+            },
+            Item::ArrayOfTables(a) => Located {
+                span: a.span(),
+                kind: ValueKind::Array(
+                    a.iter()
+                        .map(|t| Located {
+                            span: t.span(),
+                            kind: Located::from_table(t.iter()),
+                        })
+                        .collect(),
+                ),
+            },
+            Item::None => Located {
+                span: None,
+                kind: ValueKind::Other("nothing"),
+            },
+        }
+    }
+
+    fn from_value(value: &Value) -> Self {
+        let kind = match value {
+            Value::String(s) => ValueKind::String(s.value().clone()),
+            Value::Integer(_) => ValueKind::Other("integer"),
+            Value::Float(_) => ValueKind::Other("float"),
+            Value::Boolean(_) => ValueKind::Other("boolean"),
+            Value::Datetime(_) => ValueKind::Other("datetime"),
+            Value::Array(a) => ValueKind::Array(a.iter().map(Located::from_value).collect()),
+            Value::InlineTable(t) => ValueKind::Table(
+                t.iter()
+                    .map(|(k, v)| (k.to_string(), Located::from_value(v)))
+                    .collect(),
+            ),
+        };
+        Located {
+            span: value.span(),
+            kind,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found in a configuration file
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn error(span: Range<usize>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            span,
+            message,
+        }
+    }
+
+    fn warning(span: Range<usize>, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            span,
+            message,
+        }
+    }
+}
+
+/// Returns the closest known key to `key` if it is close enough to be a likely typo.
+fn suggest_key<'a>(key: &str, known: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    known
+        .map(|k| (utils::edit_distance(key, k), k))
+        .filter(|(d, k)| *d <= usize::max(1, usize::max(key.len(), k.len()) / 3))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k)
+}
+
+fn unknown_key_issue<'a>(
+    key: &str,
+    span: Range<usize>,
+    known: impl Iterator<Item = &'a str>,
+) -> ConfigIssue {
+    let message = match suggest_key(key, known) {
+        Some(s) => format!("unknown key `{key}`, did you mean `{s}`?"),
+        None => format!("unknown key `{key}`"),
+    };
+    ConfigIssue::warning(span, message)
+}
+
+/// Push an error if `value` is not of the expected type. `what` describes the value.
+fn check_type(issues: &mut Vec<ConfigIssue>, what: &str, value: &Located, expected: &str) -> bool {
+    let found = value.kind.type_name();
+    if found != expected {
+        issues.push(ConfigIssue::error(
+            value.span(),
+            format!("{what} must be a {expected}, found {found}"),
+        ));
+        return false;
+    }
+    true
+}
+
+fn validate_remote_template(issues: &mut Vec<ConfigIssue>, name: &str, value: &Located) {
+    let fields = match &value.kind {
+        ValueKind::String(_) => return,
+        ValueKind::Table(fields) => fields,
+        other => {
+            issues.push(ConfigIssue::error(
+                value.span(),
+                format!(
+                    "remote template `{name}` must be a repository url or a table of options, found {}",
+                    other.type_name()
+                ),
+            ));
+            return;
+        }
+    };
+
+    if !fields.contains_key("repo") {
+        issues.push(ConfigIssue::error(
+            value.span(),
+            format!("remote template `{name}` does not define a `repo`"),
+        ));
+    }
+
+    let known = || {
+        REMOTE_TEMPLATE_FIELDS
+            .iter()
+            .map(|k| (*k, FieldKind::String))
+            .chain(CONFIG_FIELDS.iter().map(|(k, kind, _)| (*k, *kind)))
+    };
+
+    for (key, value) in fields {
+        if key == REMOTE_TEMPLATES_OPTION {
+            issues.push(ConfigIssue::error(
+                value.span(),
+                format!("remote template `{name}` cannot define remote templates"),
+            ));
+        } else if let Some((_, kind)) = known().find(|(k, _)| k == key) {
+            check_field(issues, key, kind, value);
+        } else {
+            issues.push(unknown_key_issue(
+                key,
+                value.span(),
+                known().map(|(k, _)| k),
+            ));
+        }
+    }
+}
+
+/// Push errors if `value` does not have the type of a field of the given kind
+fn check_field(issues: &mut Vec<ConfigIssue>, key: &str, kind: FieldKind, value: &Located) {
+    match kind {
+        FieldKind::String => {
+            check_type(issues, &format!("`{key}`"), value, "string");
+        }
+        FieldKind::Integer => {
+            check_type(issues, &format!("`{key}`"), value, "integer");
+        }
+        FieldKind::StringList => {
+            if check_type(issues, &format!("`{key}`"), value, "array") {
+                if let ValueKind::Array(values) = &value.kind {
+                    for v in values {
+                        let what = format!("each element of `{key}`");
+                        check_type(issues, &what, v, "string");
+                    }
+                }
+            }
+        }
+        FieldKind::Choice(choices) => {
+            if check_type(issues, &format!("`{key}`"), value, "string") {
+                match &value.kind {
+                    ValueKind::String(found) if !choices.contains(&found.as_str()) => {
+                        issues.push(ConfigIssue::error(
+                            value.span(),
+                            format!(
+                                "`{key}` must be one of {}, found `{found}`",
+                                choices
+                                    .iter()
+                                    .map(|c| format!("`{c}`"))
+                                    .collect::<Vec<String>>()
+                                    .join(", ")
+                            ),
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        FieldKind::RemoteTemplates => {
+            if check_type(issues, &format!("`{key}`"), value, "table") {
+                if let ValueKind::Table(templates) = &value.kind {
+                    for (name, template) in templates {
+                        validate_remote_template(issues, name, template);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Validate the contents of a configuration file. Returns every problem found.
+pub fn validate(source: &str) -> Vec<ConfigIssue> {
+    let mut issues = vec![];
+
+    let document = match ImDocument::parse(source) {
+        Ok(d) => d,
+        Err(e) => {
+            issues.push(ConfigIssue::error(
+                e.span().unwrap_or(0..0),
+                e.message().to_string(),
+            ));
+            return issues;
+        }
+    };
+
+    let table = match Located::from_table(document.as_table().iter()) {
+        ValueKind::Table(t) => t,
+        _ => unreachable!("a TOML document is always a table"),
+    };
+
+    for (key, value) in &table {
+        let kind = match CONFIG_FIELDS.iter().find(|(k, _, _)| k == key) {
+            Some((_, kind, _)) => *kind,
+            None => {
+                issues.push(unknown_key_issue(
+                    key,
+                    value.span(),
+                    CONFIG_FIELDS.iter().map(|(k, _, _)| *k),
+                ));
+                continue;
+            }
+        };
+
+        check_field(&mut issues, key, kind, value);
+    }
+
+    issues.sort_by_key(|i| i.span.start);
+    issues
+}
+
+/// Convert a byte offset to a one-indexed (line, column) pair
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..usize::min(offset, source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map(|l| l.chars().count())
+        .unwrap_or(0)
+        + 1;
+    (line, column)
+}
+
+/// Print configuration issues to stderr. Returns true if any of them are errors.
+pub fn print_issues(file: &Path, source: &str, issues: &[ConfigIssue]) -> bool {
+    for issue in issues {
+        let (line, column) = line_and_column(source, issue.span.start);
+        let severity = match issue.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        eprintln!(
+            "{}:{}:{}: {}: {}",
+            file.display(),
+            line,
+            column,
+            severity,
+            issue.message
+        );
+    }
+    issues.iter().any(|i| i.severity == Severity::Error)
+}
+
+/// Check a configuration file and the files it extends, and print any problems. Returns the number
+/// of issues found.
+fn check_file(file: &Path, stack: &mut Vec<PathBuf>) -> usize {
+    let source = match fs::read_to_string(file) {
+        Ok(s) => s,
+        Err(e) => exit_with_error!("Could not read config file `{}`: {}", file.display(), e),
+    };
+    let issues = validate(&source);
+    let mut issue_count = issues.len();
+    if print_issues(file, &source, &issues) {
+        return issue_count;
+    }
+
+    let extends = match toml::from_str::<PartialConfig>(&source) {
+        Ok(c) => c.extends.unwrap_or_default(),
+        Err(_) => vec![],
+    };
+
+    stack.push(file.canonicalize().unwrap_or(file.to_path_buf()));
+    for extended in extends {
+        let extended = utils::resolve_config_path(file, &extended);
+        let canonical = extended.canonicalize().unwrap_or(extended.clone());
+        if !extended.is_file() {
+            eprintln!(
+                "{}: error: extended configuration file `{}` does not exist",
+                file.display(),
+                extended.display()
+            );
+            issue_count += 1;
+        } else if let Some(i) = stack.iter().position(|f| f == &canonical) {
+            eprintln!(
+                "{}: error: configuration files extend each other in a cycle: {}",
+                file.display(),
+                utils::format_cycle(&stack[i..], &canonical)
+            );
+            issue_count += 1;
+        } else {
+            issue_count += check_file(&extended, stack);
+        }
+    }
+    stack.pop();
+
+    issue_count
+}
+
+pub fn check(cwd: &PathBuf, global: bool, provided_config_file: Option<PathBuf>) {
+    let mut files = vec![];
+
+    let global_config_file = Config::default().config_file;
+    if global_config_file.is_file() {
+        files.push(global_config_file);
+    }
+
+    if !global {
+        let local = match provided_config_file {
+            Some(p) => Some(p),
+            None => Config::find_local_config(cwd).map(|(_, p)| p),
+        };
+        if let Some(local) = local {
+            files.push(local);
+        }
+    }
+
+    if files.is_empty() {
+        println!("No configuration files found.");
+        return;
+    }
+
+    let mut issue_count = 0;
+    for file in &files {
+        issue_count += check_file(file, &mut vec![]);
+    }
+
+    if issue_count > 0 {
+        exit_with_error!(
+            "Found {} problem{} in configuration.",
+            issue_count,
+            if issue_count == 1 { "" } else { "s" }
+        );
+    }
+
+    for file in &files {
+        println!("`{}` is valid.", file.display());
+    }
+}
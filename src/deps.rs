@@ -0,0 +1,579 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use termion::{
+    color::{self, Fg},
+    style,
+};
+
+use crate::{
+    exit_with_error, log,
+    opts::{Config, DepsArgs, DepsFormat},
+};
+
+/// Extensions tried, in order, for `\includegraphics` without an extension
+const GRAPHICS_EXTENSIONS: &[&str] = &["pdf", "png", "jpg", "jpeg", "eps"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepKind {
+    /// A TeX source file from `\input`, `\include` or `\subfile`
+    Tex,
+
+    /// A figure from `\includegraphics`
+    Graphic,
+
+    /// A bibliography database from `\bibliography` or `\addbibresource`
+    Bibliography,
+
+    /// A package or class within the document directory
+    Style,
+
+    /// A file read by the latex compiler according to the `.fls` recorder file
+    Recorded,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    /// Path relative to the document root
+    pub path: PathBuf,
+    pub kind: DepKind,
+}
+
+/// The files a document is built from, as found by following includes from the main file
+#[derive(Debug)]
+pub struct DepGraph {
+    pub root: PathBuf,
+    pub main_file: PathBuf,
+
+    /// The dependencies of each parsed TeX file
+    pub edges: BTreeMap<PathBuf, Vec<Dependency>>,
+}
+
+/// Remove `.` components and resolve `..` components without touching the file system
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir if out.file_name().is_some() => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Environments whose contents are typeset verbatim, such that `%` does not start a comment
+pub const VERBATIM_ENVIRONMENTS: &[&str] =
+    &["verbatim", "verbatim*", "Verbatim", "lstlisting", "minted"];
+
+/// Find the start of the comment on a line. Escaped percent signs and the contents of `\verb`
+/// are skipped.
+fn find_comment(line: &str) -> Option<usize> {
+    let mut i = 0;
+    while let Some(c) = line[i..].chars().next() {
+        match c {
+            '%' => return Some(i),
+            '\\' => {
+                let rest = &line[i + 1..];
+                if let Some(after) = rest.strip_prefix("verb") {
+                    let after = after.strip_prefix('*').unwrap_or(after);
+                    if let Some(delimiter) = after.chars().next().filter(|c| !c.is_alphabetic()) {
+                        let body = line.len() - after.len() + delimiter.len_utf8();
+                        match line[body..].find(delimiter) {
+                            Some(j) => i = body + j + delimiter.len_utf8(),
+                            None => return None,
+                        }
+                        continue;
+                    }
+                }
+                // Skip the escaped character
+                i += 1 + rest.chars().next().map(char::len_utf8).unwrap_or(0);
+            }
+            c => i += c.len_utf8(),
+        }
+    }
+    None
+}
+
+/// The verbatim environment a line of code begins without ending it, if any
+pub fn begins_verbatim(code: &str) -> Option<&'static str> {
+    VERBATIM_ENVIRONMENTS.iter().copied().find(|env| {
+        code.contains(&format!("\\begin{{{env}}}")) && !code.contains(&format!("\\end{{{env}}}"))
+    })
+}
+
+/// Remove comments from TeX source, except in verbatim environments. The `%` of each comment is
+/// kept, such that the line break stays ignored. Lines with only a comment are removed, unless
+/// `keep_lines` is set to keep line numbers intact.
+pub fn strip_comments(source: &str, keep_lines: bool) -> String {
+    let mut lines = vec![];
+    let mut verbatim: Option<&str> = None;
+
+    for line in source.lines() {
+        if let Some(env) = verbatim {
+            if line.contains(&format!("\\end{{{env}}}")) {
+                verbatim = None;
+            }
+            lines.push(line.to_string());
+            continue;
+        }
+
+        let code = match find_comment(line) {
+            Some(i) if !keep_lines && line[..i].trim().is_empty() => continue,
+            Some(i) => format!("{}%", &line[..i]),
+            None => line.to_string(),
+        };
+
+        verbatim = begins_verbatim(&code);
+        lines.push(code);
+    }
+
+    let mut out = lines.join("\n");
+    if source.ends_with('\n') {
+        out.push('\n');
+    }
+    out
+}
+
+#[test]
+fn test_strip_comments() {
+    let source = r"Text % comment
+% Only a comment
+50\% of \verb|a % b| % c
+\begin{verbatim}
+% kept
+\end{verbatim}
+\\% comment after a line break
+";
+    assert_eq!(
+        strip_comments(source, false),
+        r"Text %
+50\% of \verb|a % b| %
+\begin{verbatim}
+% kept
+\end{verbatim}
+\\%
+"
+    );
+    assert_eq!(strip_comments(source, true).lines().nth(1), Some("%"));
+}
+
+/// Read a brace delimited group starting at `i`. Returns the contents and the index after the
+/// closing brace.
+fn read_group(s: &str, i: usize, open: char, close: char) -> Option<(&str, usize)> {
+    let mut chars = s[i..].char_indices();
+    match chars.next() {
+        Some((_, c)) if c == open => {}
+        _ => return None,
+    }
+    let mut depth = 1;
+    for (j, c) in chars {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some((&s[i + 1..i + j], i + j + 1));
+            }
+        }
+    }
+    None
+}
+
+fn skip_whitespace(s: &str, i: usize) -> usize {
+    i + s[i..].len() - s[i..].trim_start().len()
+}
+
+/// A command found in TeX source along with its first mandatory argument
+#[derive(Debug, PartialEq, Eq)]
+pub struct TexCommand<'a> {
+    pub name: &'a str,
+    pub arg: &'a str,
+
+    /// Byte offsets of the full command in the source
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Find all uses of the given commands which have a mandatory argument. Optional arguments are
+/// skipped. The source should not contain comments.
+pub fn find_commands<'a>(source: &'a str, names: &[&str]) -> Vec<TexCommand<'a>> {
+    let mut commands = vec![];
+    let mut search_from = 0;
+
+    while let Some(offset) = source[search_from..].find('\\') {
+        let start = search_from + offset;
+        let name_start = start + 1;
+        let rest = &source[name_start..];
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let name = &rest[..name_len];
+
+        // Skip the single character of control symbols like `\\` and `\%`
+        search_from = name_start
+            + match name_len {
+                0 => rest.chars().next().map(char::len_utf8).unwrap_or(0),
+                n => n,
+            };
+
+        if !names.contains(&name) {
+            continue;
+        }
+
+        let mut i = skip_whitespace(source, name_start + name_len);
+        while let Some((_, next)) = read_group(source, i, '[', ']') {
+            i = skip_whitespace(source, next);
+        }
+        if let Some((arg, end)) = read_group(source, i, '{', '}') {
+            commands.push(TexCommand {
+                name,
+                arg,
+                start,
+                end,
+            });
+            search_from = end;
+        }
+    }
+
+    commands
+}
+
+#[test]
+fn test_find_commands() {
+    let source = r"\documentclass[a4paper]{article}
+\input{chapters/intro} \include {chapters/method}
+\includegraphics[width=\textwidth]{figures/plot}
+\inputenc{nope} \bibliography{refs,more}";
+    let found: Vec<(&str, &str)> = find_commands(
+        source,
+        &["input", "include", "includegraphics", "bibliography"],
+    )
+    .iter()
+    .map(|c| (c.name, c.arg))
+    .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("input", "chapters/intro"),
+            ("include", "chapters/method"),
+            ("includegraphics", "figures/plot"),
+            ("bibliography", "refs,more"),
+        ]
+    );
+}
+
+/// The directories in the argument of `\graphicspath`, like `{figures/}{images/}`
+pub fn split_graphics_paths(arg: &str) -> Vec<&str> {
+    let mut dirs = vec![];
+    let mut i = skip_whitespace(arg, 0);
+    while let Some((dir, next)) = read_group(arg, i, '{', '}') {
+        dirs.push(dir);
+        i = skip_whitespace(arg, next);
+    }
+    dirs
+}
+
+/// Add `extension` to the path unless it already has one
+pub fn with_default_extension(path: &str, extension: &str) -> PathBuf {
+    let path = PathBuf::from(path.trim());
+    match path.extension() {
+        Some(_) => path,
+        None => path.with_extension(extension),
+    }
+}
+
+impl DepGraph {
+    /// Build the dependency graph by parsing the TeX sources starting from `main_file`. If
+    /// `recorder` is set, files listed in that `.fls` file produced by the `-recorder` flag are
+    /// added as dependencies of the main file.
+    pub fn build(root: &Path, main_file: &Path, recorder: Option<&Path>) -> Self {
+        let mut graph = Self {
+            root: root.to_path_buf(),
+            main_file: normalize(main_file),
+            edges: BTreeMap::new(),
+        };
+
+        let main_file = graph.main_file.clone();
+        let mut graphics_paths = vec![PathBuf::new()];
+        graph.parse_file(&main_file, &mut graphics_paths);
+
+        if let Some(fls_file) = recorder {
+            let known: HashSet<PathBuf> = graph.files().into_iter().map(|d| d.path).collect();
+            let recorded: Vec<Dependency> = graph
+                .recorded_files(fls_file)
+                .into_iter()
+                .filter(|d| !known.contains(&d.path))
+                .collect();
+            graph.edges.entry(main_file).or_default().extend(recorded);
+        }
+
+        graph
+    }
+
+    fn parse_file(&mut self, file: &Path, graphics_paths: &mut Vec<PathBuf>) {
+        if self.edges.contains_key(file) {
+            return;
+        }
+
+        let source = match fs::read_to_string(self.root.join(file)) {
+            Ok(s) => strip_comments(&s, true),
+            Err(_) => return,
+        };
+
+        let commands = find_commands(
+            &source,
+            &[
+                "input",
+                "include",
+                "subfile",
+                "includegraphics",
+                "graphicspath",
+                "bibliography",
+                "addbibresource",
+                "usepackage",
+                "RequirePackage",
+                "documentclass",
+            ],
+        );
+
+        let mut deps: Vec<Dependency> = vec![];
+        for cmd in &commands {
+            match cmd.name {
+                "input" | "include" | "subfile" => deps.push(Dependency {
+                    path: normalize(&with_default_extension(cmd.arg, "tex")),
+                    kind: DepKind::Tex,
+                }),
+                "includegraphics" => deps.push(Dependency {
+                    path: self.find_graphic(cmd.arg.trim(), graphics_paths),
+                    kind: DepKind::Graphic,
+                }),
+                "graphicspath" => graphics_paths.extend(
+                    split_graphics_paths(cmd.arg)
+                        .into_iter()
+                        .map(|dir| normalize(Path::new(dir))),
+                ),
+                "bibliography" => deps.extend(cmd.arg.split(',').map(|b| Dependency {
+                    path: normalize(&with_default_extension(b, "bib")),
+                    kind: DepKind::Bibliography,
+                })),
+                "addbibresource" => deps.push(Dependency {
+                    path: normalize(Path::new(cmd.arg.trim())),
+                    kind: DepKind::Bibliography,
+                }),
+                "usepackage" | "RequirePackage" | "documentclass" => {
+                    let extension = match cmd.name {
+                        "documentclass" => "cls",
+                        _ => "sty",
+                    };
+                    // Only packages in the document directory are dependencies of the document
+                    deps.extend(
+                        cmd.arg
+                            .split(',')
+                            .map(|p| normalize(&PathBuf::from(p.trim()).with_extension(extension)))
+                            .filter(|p| self.root.join(p).is_file())
+                            .map(|path| Dependency {
+                                path,
+                                kind: DepKind::Style,
+                            }),
+                    )
+                }
+                _ => unreachable!("only searched for the commands above"),
+            }
+        }
+
+        self.edges.insert(file.to_path_buf(), deps.clone());
+
+        for dep in deps {
+            if matches!(dep.kind, DepKind::Tex | DepKind::Style) {
+                self.parse_file(&dep.path, graphics_paths);
+            }
+        }
+    }
+
+    /// Find the file `\includegraphics{name}` refers to, searching the directories of
+    /// `\graphicspath`
+    pub fn find_graphic(&self, name: &str, graphics_paths: &[PathBuf]) -> PathBuf {
+        let name = Path::new(name);
+        let candidates: Vec<PathBuf> = match name.extension() {
+            Some(_) => graphics_paths.iter().map(|d| d.join(name)).collect(),
+            None => graphics_paths
+                .iter()
+                .flat_map(|d| {
+                    GRAPHICS_EXTENSIONS
+                        .iter()
+                        .map(move |e| d.join(name).with_extension(e))
+                })
+                .collect(),
+        };
+        let path = candidates
+            .iter()
+            .find(|p| self.root.join(p).is_file())
+            .unwrap_or(&candidates[0]);
+        normalize(path)
+    }
+
+    /// Files within the document root read during the last compilation according to the `.fls`
+    /// recorder file.
+    fn recorded_files(&self, fls_file: &Path) -> Vec<Dependency> {
+        let contents = match fs::read_to_string(fls_file) {
+            Ok(c) => c,
+            Err(e) => exit_with_error!(
+                "Could not read recorder file '{}': {}. Compile with the `-recorder` flag to create it.",
+                fls_file.display(),
+                e
+            ),
+        };
+
+        let mut pwd = self.root.clone();
+        let mut files = vec![];
+        for line in contents.lines() {
+            if let Some(dir) = line.strip_prefix("PWD ") {
+                pwd = PathBuf::from(dir);
+            } else if let Some(file) = line.strip_prefix("INPUT ") {
+                let path = normalize(&pwd.join(file));
+                // Files outside of the document are part of the latex installation
+                if let Ok(rel) = path.strip_prefix(normalize(&self.root)) {
+                    let rel = rel.to_path_buf();
+                    let is_build_file = matches!(
+                        rel.extension().and_then(|e| e.to_str()),
+                        Some("aux" | "toc" | "out" | "bbl" | "lof" | "lot" | "nav" | "snm")
+                    );
+                    if !is_build_file && !files.iter().any(|d: &Dependency| d.path == rel) {
+                        files.push(Dependency {
+                            path: rel,
+                            kind: DepKind::Recorded,
+                        });
+                    }
+                }
+            }
+        }
+        files
+    }
+
+    /// All files the document depends on, starting with the main file, in the order they are
+    /// included.
+    pub fn files(&self) -> Vec<Dependency> {
+        let mut files = vec![Dependency {
+            path: self.main_file.clone(),
+            kind: DepKind::Tex,
+        }];
+        let mut seen = HashSet::new();
+        seen.insert(self.main_file.clone());
+        self.collect_files(&self.main_file, &mut seen, &mut files);
+        files
+    }
+
+    fn collect_files(&self, file: &Path, seen: &mut HashSet<PathBuf>, files: &mut Vec<Dependency>) {
+        for dep in self.edges.get(file).into_iter().flatten() {
+            if seen.insert(dep.path.clone()) {
+                files.push(dep.clone());
+                self.collect_files(&dep.path, seen, files);
+            }
+        }
+    }
+
+    /// Check whether a file in the graph exists
+    pub fn exists(&self, path: &Path) -> bool {
+        self.root.join(path).is_file()
+    }
+
+    fn print_tree(&self, file: &Path, level: usize, stack: &mut Vec<PathBuf>) {
+        for dep in self.edges.get(file).into_iter().flatten() {
+            let name = dep.path.display();
+            let indent = "  ".repeat(level);
+            if !self.exists(&dep.path) {
+                println!(
+                    "{indent}{}{name} (missing){}",
+                    Fg(color::Red),
+                    Fg(color::Reset)
+                );
+            } else if self.edges.contains_key(&dep.path) {
+                println!(
+                    "{indent}{}{}{name}{}{}",
+                    style::Bold,
+                    Fg(color::Blue),
+                    Fg(color::Reset),
+                    style::Reset
+                );
+                if !stack.contains(&dep.path) {
+                    stack.push(dep.path.clone());
+                    self.print_tree(&dep.path, level + 1, stack);
+                    stack.pop();
+                }
+            } else {
+                println!("{indent}{name}");
+            }
+        }
+    }
+
+    fn make_rule(&self) -> String {
+        let target = self.main_file.with_extension("pdf");
+        let escape = |p: &Path| p.display().to_string().replace(' ', "\\ ");
+        let deps: Vec<String> = self.files().iter().map(|d| escape(&d.path)).collect();
+        format!("{}: {}\n", escape(&target), deps.join(" \\\n    "))
+    }
+
+    fn dot_graph(&self) -> String {
+        let mut out = format!("digraph \"{}\" {{\n", self.main_file.display());
+        for (file, deps) in &self.edges {
+            for dep in deps {
+                let style = match self.exists(&dep.path) {
+                    true => "",
+                    false => " [style=dashed]",
+                };
+                out += &format!(
+                    "    \"{}\" -> \"{}\"{};\n",
+                    file.display(),
+                    dep.path.display(),
+                    style
+                );
+            }
+        }
+        out + "}\n"
+    }
+}
+
+pub fn deps(config: Config, args: DepsArgs) {
+    let main_file = match args.main_file {
+        Some(f) => PathBuf::from(f),
+        None => config.main_file.clone(),
+    };
+
+    if !config.root.join(&main_file).is_file() {
+        exit_with_error!("Cannot find main file `{}`.", main_file.display());
+    }
+
+    let fls_file = log::aux_file(&config, &main_file, "fls");
+    let graph = DepGraph::build(
+        &config.root,
+        &main_file,
+        args.recorder.then_some(fls_file.as_path()),
+    );
+
+    match args.format {
+        DepsFormat::Tree => {
+            println!(
+                "{}{}{}{}{}",
+                style::Bold,
+                Fg(color::Blue),
+                graph.main_file.display(),
+                Fg(color::Reset),
+                style::Reset
+            );
+            graph.print_tree(&graph.main_file, 1, &mut vec![graph.main_file.clone()]);
+        }
+        DepsFormat::List => {
+            for dep in graph.files() {
+                println!("{}", dep.path.display());
+            }
+        }
+        DepsFormat::Make => print!("{}", graph.make_rule()),
+        DepsFormat::Dot => print!("{}", graph.dot_graph()),
+    }
+}
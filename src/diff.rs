@@ -0,0 +1,541 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use termion::color::{self, Fg};
+
+use crate::{
+    compile, deps, exit_with_error, flatten, log,
+    opts::{CompileArgs, Config, DiffArgs, Differ},
+    utils,
+};
+
+/// Environments in which the word differ does not mark changes, besides verbatim environments
+const RAW_ENVIRONMENTS: &[&str] = &[
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "gather",
+    "gather*",
+    "multline",
+    "multline*",
+    "displaymath",
+    "math",
+    "tabular",
+];
+
+/// Commands marking changes, added to the preamble by the word differ
+const DIFF_PREAMBLE: &str = r"\RequirePackage{xcolor}
+\RequirePackage[normalem]{ulem}
+\providecommand{\DIFadd}[1]{{\color{blue}\uwave{#1}}}
+\providecommand{\DIFdel}[1]{{\color{red}\sout{#1}}}
+";
+
+/// Run git in `dir`, exiting with its error output if it fails
+fn git(dir: &Path, args: &[&str]) -> Vec<u8> {
+    let output = match Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(o) => o,
+        Err(e) => exit_with_error!("Error running git command: {}", e),
+    };
+    if !output.status.success() {
+        exit_with_error!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    output.stdout
+}
+
+/// Extract the files of the repository at revision `rev` into `dir`. Returns the directory of
+/// the document within it.
+fn checkout(root: &Path, rev: &str, dir: &Path) -> PathBuf {
+    let prefix = String::from_utf8_lossy(&git(root, &["rev-parse", "--show-prefix"]))
+        .trim()
+        .to_string();
+    let tarball = git(root, &["archive", "--format=tar", rev]);
+
+    utils::create_dir_all(dir);
+    if let Err(e) = tar::Archive::new(tarball.as_slice()).unpack(dir) {
+        exit_with_error!("Could not extract revision `{}`: {}", rev, e);
+    }
+    dir.join(prefix)
+}
+
+/// A change needed to turn one sequence into another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Keep(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Lengths of the longest common subsequences of `a` and each prefix of `b`, or of each suffix
+/// of `b` if `reverse` is set. Only one row of the table is kept, such that the memory used is
+/// linear.
+fn lcs_lengths<T: PartialEq>(a: &[T], b: &[T], reverse: bool) -> Vec<usize> {
+    let index = |i: usize, len: usize| if reverse { len - 1 - i } else { i };
+    let mut row = vec![0; b.len() + 1];
+    for i in 0..a.len() {
+        let x = &a[index(i, a.len())];
+        let mut diagonal = 0;
+        for j in 0..b.len() {
+            let above = row[j + 1];
+            row[j + 1] = match x == &b[index(j, b.len())] {
+                true => diagonal + 1,
+                false => above.max(row[j]),
+            };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+/// Add the edits turning `a` into `b` to `out`, splitting the problem in halves with
+/// Hirschberg's algorithm. The elements start at `a_start` and `b_start` in the whole sequences.
+fn split_edits<T: PartialEq>(
+    a: &[T],
+    b: &[T],
+    a_start: usize,
+    b_start: usize,
+    out: &mut Vec<Edit>,
+) {
+    let insert_all = |out: &mut Vec<Edit>, range: std::ops::Range<usize>| {
+        out.extend(range.map(|j| Edit::Insert(b_start + j)))
+    };
+    match a.len() {
+        0 => insert_all(out, 0..b.len()),
+        _ if b.is_empty() => out.extend((0..a.len()).map(|i| Edit::Delete(a_start + i))),
+        1 => match b.iter().position(|y| y == &a[0]) {
+            Some(k) => {
+                insert_all(out, 0..k);
+                out.push(Edit::Keep(a_start, b_start + k));
+                insert_all(out, k + 1..b.len());
+            }
+            None => {
+                out.push(Edit::Delete(a_start));
+                insert_all(out, 0..b.len());
+            }
+        },
+        _ => {
+            let mid = a.len() / 2;
+            let forward = lcs_lengths(&a[..mid], b, false);
+            let backward = lcs_lengths(&a[mid..], b, true);
+            let split = (0..=b.len())
+                .max_by_key(|&j| (forward[j] + backward[b.len() - j], std::cmp::Reverse(j)))
+                .unwrap_or(0);
+            split_edits(&a[..mid], &b[..split], a_start, b_start, out);
+            split_edits(&a[mid..], &b[split..], a_start + mid, b_start + split, out);
+        }
+    }
+}
+
+/// The edits turning `a` into `b` along a longest common subsequence
+fn edits<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut out: Vec<Edit> = (0..prefix).map(|i| Edit::Keep(i, i)).collect();
+    split_edits(
+        &a[prefix..a.len() - suffix],
+        &b[prefix..b.len() - suffix],
+        prefix,
+        prefix,
+        &mut out,
+    );
+    out.extend((0..suffix).map(|k| Edit::Keep(a.len() - suffix + k, b.len() - suffix + k)));
+    out
+}
+
+#[test]
+fn test_edits() {
+    let a: Vec<char> = "abcd".chars().collect();
+    let b: Vec<char> = "axcde".chars().collect();
+    assert_eq!(
+        edits(&a, &b),
+        vec![
+            Edit::Keep(0, 0),
+            Edit::Delete(1),
+            Edit::Insert(1),
+            Edit::Keep(2, 2),
+            Edit::Keep(3, 3),
+            Edit::Insert(4),
+        ]
+    );
+
+    // The edits keep a longest common subsequence, and apply in order
+    let a: Vec<char> = "ABCBDABXYZ".chars().collect();
+    let b: Vec<char> = "BDCABAZYX".chars().collect();
+    let edits = edits(&a, &b);
+    let kept = edits.iter().filter(|e| matches!(e, Edit::Keep(..))).count();
+    assert_eq!(kept, 5);
+    let (mut i, mut j) = (0, 0);
+    for edit in edits {
+        match edit {
+            Edit::Keep(x, y) => {
+                assert_eq!((x, y), (i, j));
+                assert_eq!(a[x], b[y]);
+                (i, j) = (i + 1, j + 1);
+            }
+            Edit::Delete(x) => {
+                assert_eq!(x, i);
+                i += 1;
+            }
+            Edit::Insert(y) => {
+                assert_eq!(y, j);
+                j += 1;
+            }
+        }
+    }
+    assert_eq!((i, j), (a.len(), b.len()));
+}
+
+/// Tracks whether words of a source can be marked, which they cannot in math and raw environments
+#[derive(Default)]
+struct Mode {
+    math: bool,
+    environment: Option<&'static str>,
+}
+
+impl Mode {
+    /// Whether `word` can be wrapped in a marking command. Only plain text is marked.
+    fn markable(&self, word: &str) -> bool {
+        !self.math
+            && self.environment.is_none()
+            && word
+                .chars()
+                .all(|c| c.is_alphanumeric() || ".,;:!?'\"()-".contains(c))
+    }
+
+    fn update(&mut self, word: &str) {
+        if let Some(env) = self.environment {
+            if word.contains(&format!("\\end{{{env}}}")) {
+                self.environment = None;
+            }
+            return;
+        }
+        if let Some(env) = RAW_ENVIRONMENTS
+            .iter()
+            .chain(deps::VERBATIM_ENVIRONMENTS)
+            .find(|env| word.contains(&format!("\\begin{{{env}}}")))
+        {
+            self.environment = Some(env);
+            return;
+        }
+
+        let code = word.replace("\\$", "");
+        if code.contains("\\[") || code.contains("\\(") {
+            self.math = true;
+        }
+        if code.contains("\\]") || code.contains("\\)") {
+            self.math = false;
+        }
+        // Runs of dollar signs like `$$` toggle math mode once
+        let runs = code.split(|c| c != '$').filter(|r| !r.is_empty()).count();
+        if runs % 2 == 1 {
+            self.math = !self.math;
+        }
+    }
+}
+
+#[test]
+fn test_mode() {
+    let mut mode = Mode::default();
+    assert!(mode.markable("word,"));
+    assert!(!mode.markable("\\emph{word}"));
+    mode.update("$x");
+    assert!(!mode.markable("y"));
+    mode.update("y$");
+    assert!(mode.markable("z"));
+    mode.update("\\begin{equation}");
+    assert!(!mode.markable("a"));
+    mode.update("\\end{equation}");
+    assert!(mode.markable("a"));
+}
+
+/// Split text into words, each with the whitespace before it. Whitespace after the last word is
+/// returned separately.
+fn words(text: &str) -> (Vec<(&str, &str)>, &str) {
+    let mut out = vec![];
+    let mut rest = text;
+    loop {
+        let word_start = rest.len() - rest.trim_start().len();
+        if word_start == rest.len() {
+            return (out, rest);
+        }
+        let word_end = rest[word_start..]
+            .find(char::is_whitespace)
+            .map(|i| word_start + i)
+            .unwrap_or(rest.len());
+        out.push((&rest[..word_start], &rest[word_start..word_end]));
+        rest = &rest[word_end..];
+    }
+}
+
+/// Mark the words changed between two pieces of text
+fn diff_words(old: &str, new: &str, old_mode: &mut Mode, new_mode: &mut Mode) -> String {
+    let (old_words, _) = words(old);
+    let (new_words, trailing) = words(new);
+    let a: Vec<&str> = old_words.iter().map(|(_, w)| *w).collect();
+    let b: Vec<&str> = new_words.iter().map(|(_, w)| *w).collect();
+
+    let mut out = String::new();
+    for edit in edits(&a, &b) {
+        match edit {
+            Edit::Keep(i, j) => {
+                old_mode.update(a[i]);
+                new_mode.update(b[j]);
+                out += new_words[j].0;
+                out += b[j];
+            }
+            Edit::Delete(i) => {
+                if old_mode.markable(a[i]) {
+                    out += &match out.is_empty() {
+                        true => format!("\\DIFdel{{{}}} ", a[i]),
+                        false => format!(" \\DIFdel{{{}}}", a[i]),
+                    };
+                }
+                old_mode.update(a[i]);
+            }
+            Edit::Insert(j) => {
+                out += new_words[j].0;
+                match new_mode.markable(b[j]) {
+                    true => out += &format!("\\DIFadd{{{}}}", b[j]),
+                    false => out += b[j],
+                }
+                new_mode.update(b[j]);
+            }
+        }
+    }
+    out + trailing
+}
+
+/// Split a source at the line containing `\begin{document}`, which ends the first part
+fn split_preamble(source: &str) -> Option<(&str, &str)> {
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        offset += line.len();
+        if deps::strip_comments(line, true).contains("\\begin{document}") {
+            return Some(source.split_at(offset));
+        }
+    }
+    None
+}
+
+/// Mark the changes between two flattened sources with the built-in differ. Lines are compared
+/// first, and the words of changed lines are compared next. The preamble of the new source is
+/// used as is.
+fn diff_sources(old: &str, new: &str) -> String {
+    let (Some((_, old_body)), Some((preamble, new_body))) =
+        (split_preamble(old), split_preamble(new))
+    else {
+        exit_with_error!("Could not find `\\begin{{document}}` in the document.");
+    };
+    let begin = preamble
+        .rfind("\\begin{document}")
+        .unwrap_or(preamble.len());
+
+    let old_body = deps::strip_comments(old_body, false);
+    let new_body = deps::strip_comments(new_body, false);
+    let old_lines: Vec<&str> = old_body.lines().collect();
+    let new_lines: Vec<&str> = new_body.lines().collect();
+
+    let mut out = format!(
+        "{}{DIFF_PREAMBLE}{}",
+        &preamble[..begin],
+        &preamble[begin..]
+    );
+    let (mut old_mode, mut new_mode) = (Mode::default(), Mode::default());
+    let line_edits = edits(&old_lines, &new_lines);
+    let mut k = 0;
+    while k < line_edits.len() {
+        if let Edit::Keep(i, j) = line_edits[k] {
+            words(old_lines[i])
+                .0
+                .iter()
+                .for_each(|(_, w)| old_mode.update(w));
+            words(new_lines[j])
+                .0
+                .iter()
+                .for_each(|(_, w)| new_mode.update(w));
+            out += new_lines[j];
+            out.push('\n');
+            k += 1;
+            continue;
+        }
+
+        // Compare the words of a run of changed lines
+        let (mut deleted, mut inserted) = (vec![], vec![]);
+        while let Some(edit) = line_edits.get(k) {
+            match *edit {
+                Edit::Delete(i) => deleted.push(old_lines[i]),
+                Edit::Insert(j) => inserted.push(new_lines[j]),
+                Edit::Keep(..) => break,
+            }
+            k += 1;
+        }
+        out += &diff_words(
+            &deleted.join("\n"),
+            &inserted.join("\n"),
+            &mut old_mode,
+            &mut new_mode,
+        );
+        out.push('\n');
+    }
+    out
+}
+
+#[test]
+fn test_diff_sources() {
+    let old =
+        "\\documentclass{article}\n\\begin{document}\nA short text.\n$x = 1$\n\\end{document}\n";
+    let new = "\\documentclass{article}\n\\begin{document}\nA longer text.\n$x = 2$ % changed\n\\end{document}\n";
+    assert_eq!(
+        diff_sources(old, new),
+        format!(
+            "\\documentclass{{article}}\n{DIFF_PREAMBLE}\\begin{{document}}\nA \\DIFdel{{short}} \\DIFadd{{longer}} text.\n$x = 2$ %\n\\end{{document}}\n"
+        )
+    );
+
+    // Verbatim text is neither marked nor stripped of comments
+    let old =
+        "\\begin{document}\n\\begin{Verbatim}\nold % kept\n\\end{Verbatim}\n\\end{document}\n";
+    let new =
+        "\\begin{document}\n\\begin{Verbatim}\nnew % kept\n\\end{Verbatim}\n\\end{document}\n";
+    assert!(diff_sources(old, new)
+        .ends_with("\\begin{Verbatim}\nnew % kept\n\\end{Verbatim}\n\\end{document}\n"));
+}
+
+/// Mark the changes between `old` and `new` with latexdiff
+fn latexdiff(old: &Path, new: &Path) -> String {
+    let output = match Command::new("latexdiff")
+        .arg("--graphics-markup=new-only")
+        .arg(old)
+        .arg(new)
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(o) => o,
+        Err(e) => exit_with_error!("Could not run latexdiff: {}", e),
+    };
+    if !output.status.success() {
+        exit_with_error!(
+            "latexdiff failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+/// Quote a string for the shell
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+pub fn diff(config: Config, args: DiffArgs) {
+    let main_file = deps::normalize(&match args.main_file {
+        Some(f) => PathBuf::from(f),
+        None => config.main_file.clone(),
+    });
+
+    let dir = config.temp_dir.join("diff");
+    if dir.exists() {
+        utils::remove_dir_all(&dir);
+    }
+    let build_dir = dir.join("build");
+    utils::create_dir_all(&build_dir);
+
+    let old_root = checkout(&config.root, &args.old, &dir.join("old"));
+    let new_root = match &args.new {
+        Some(rev) => checkout(&config.root, rev, &dir.join("new")),
+        None => config.root.clone(),
+    };
+    let new_name = args.new.as_deref().unwrap_or("the working tree");
+
+    let mut sources = vec![];
+    for (root, name, file) in [
+        (&old_root, args.old.as_str(), "old.tex"),
+        (&new_root, new_name, "new.tex"),
+    ] {
+        if !root.join(&main_file).is_file() {
+            exit_with_error!(
+                "Cannot find main file `{}` in {}.",
+                main_file.display(),
+                name
+            );
+        }
+        // Files which are not inlined are found through `TEXINPUTS` when compiling
+        let (source, _) = flatten::flatten_document(root, &main_file, root, None);
+        utils::write(&build_dir.join(file), &source);
+        sources.push(source);
+    }
+
+    let differ = args
+        .differ
+        .unwrap_or_else(|| match utils::find_executable("latexdiff") {
+            Some(_) => Differ::Latexdiff,
+            None => Differ::Words,
+        });
+    let marked = match differ {
+        Differ::Latexdiff => latexdiff(&build_dir.join("old.tex"), &build_dir.join("new.tex")),
+        Differ::Words => diff_sources(&sources[0], &sources[1]),
+    };
+    utils::write(&build_dir.join("diff.tex"), marked);
+
+    println!(
+        "{}Compiling changes from `{}` to {}.{}",
+        Fg(color::Blue),
+        args.old,
+        match &args.new {
+            Some(rev) => format!("`{rev}`"),
+            None => new_name.to_string(),
+        },
+        Fg(color::Reset)
+    );
+
+    // Files only in the old revision, like removed figures, are found after the new ones. The
+    // trailing separator keeps the default search path, and paths added by blatex are kept.
+    let search_path = quote(&format!("{}:{}:", new_root.display(), old_root.display()));
+    let diff_config = Config {
+        root: build_dir.clone(),
+        compile_cmd: format!(
+            "export TEXINPUTS={search_path}\"$TEXINPUTS\" BIBINPUTS={search_path} && {}",
+            config.compile_cmd
+        ),
+        ..config.clone()
+    };
+    let diff_file = PathBuf::from("diff.tex");
+    let compile_args = CompileArgs {
+        force: true,
+        quiet: true,
+        ..Default::default()
+    };
+    compile::compile_file(diff_config.clone(), diff_file.clone(), &compile_args);
+
+    let pdf = log::find_pdf_file(&diff_config, &diff_file);
+    if !pdf.is_file() {
+        exit_with_error!("The compiler did not produce `{}`.", pdf.display());
+    }
+    let out = utils::parrent(&log::find_pdf_file(&config, &main_file)).join("diff.pdf");
+    utils::copy(&pdf, &out);
+
+    println!(
+        "{}Wrote `{}`.{}",
+        Fg(color::Green),
+        out.strip_prefix(&config.root).unwrap_or(&out).display(),
+        Fg(color::Reset)
+    );
+}
@@ -0,0 +1,342 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use termion::color::{self, Fg};
+
+use crate::{
+    deps::{self, find_commands, DepGraph, TexCommand},
+    exit_with_error, log,
+    opts::{Config, FlattenArgs},
+    utils,
+};
+
+/// Commands handled while flattening
+const COMMANDS: &[&str] = &[
+    "input",
+    "include",
+    "subfile",
+    "includeonly",
+    "includegraphics",
+    "graphicspath",
+    "bibliography",
+    "addbibresource",
+];
+
+/// The lines of a subfile between `\begin{document}` and `\end{document}`, along with the line
+/// number of the first of them. Files without a document environment are returned whole.
+fn document_body(source: &str) -> (Vec<&str>, usize) {
+    let lines: Vec<&str> = source.lines().collect();
+    let code = |l: &str| deps::strip_comments(l, true);
+    let begin = lines
+        .iter()
+        .position(|l| code(l).contains("\\begin{document}"));
+    let end = lines
+        .iter()
+        .rposition(|l| code(l).contains("\\end{document}"));
+    match (begin, end) {
+        (Some(b), Some(e)) if b < e => (lines[b + 1..e].to_vec(), b + 2),
+        _ => (lines, 1),
+    }
+}
+
+#[test]
+fn test_document_body() {
+    let source = "\\documentclass[../main]{subfiles}\n\\begin{document}\nBody\n\\end{document}\n";
+    assert_eq!(document_body(source), (vec!["Body"], 3));
+    assert_eq!(document_body("Text\nMore"), (vec!["Text", "More"], 1));
+}
+
+/// The command with its argument replaced by `arg`
+fn replace_arg(line: &str, cmd: &TexCommand, arg: &str) -> String {
+    let arg_start = cmd.end - 1 - cmd.arg.len();
+    format!("{}{arg}}}", &line[cmd.start..arg_start])
+}
+
+struct Flattener {
+    graph: DepGraph,
+
+    /// Prepended to paths of files which are not inlined, when the output is written to another
+    /// directory than the document root
+    prefix: PathBuf,
+
+    graphics_paths: Vec<PathBuf>,
+
+    /// Files listed by `\includeonly`, without extension
+    include_only: Option<Vec<PathBuf>>,
+
+    /// Contents of the `.bbl` file to inline in place of `\bibliography`
+    bbl: Option<String>,
+
+    /// Files currently being inlined, to detect cycles
+    stack: Vec<PathBuf>,
+
+    /// Number of files inlined
+    inlined: usize,
+}
+
+impl Flattener {
+    fn prefixed(&self, path: &str) -> String {
+        match self.prefix.as_os_str().is_empty() {
+            true => path.to_string(),
+            false => self.prefix.join(path.trim()).display().to_string(),
+        }
+    }
+
+    /// Inline a TeX file between comments recording where its lines come from. `from` is the file
+    /// and line of the command including it.
+    fn inline(&mut self, file: &Path, from: (&Path, usize), body_only: bool) -> Option<String> {
+        if self.stack.iter().any(|f| f == file) {
+            self.stack.push(file.to_path_buf());
+            let cycle = utils::format_cycle(&self.stack, file);
+            self.stack.pop();
+            warn(&format!(
+                "Not inlining `{}`, which includes itself: {cycle}",
+                file.display()
+            ));
+            return None;
+        }
+        let source = match fs::read_to_string(self.graph.root.join(file)) {
+            Ok(s) => s,
+            Err(e) => {
+                warn(&format!("Could not inline `{}`: {e}", file.display()));
+                return None;
+            }
+        };
+        let (lines, first) = match body_only {
+            true => document_body(&source),
+            false => (source.lines().collect(), 1),
+        };
+
+        self.stack.push(file.to_path_buf());
+        let body = self.flatten_lines(file, &lines, first);
+        self.stack.pop();
+        self.inlined += 1;
+
+        Some(format!(
+            "%\n%% >>> {}:{first}\n{body}%% <<< {}, back in {}:{}\n",
+            file.display(),
+            file.display(),
+            from.0.display(),
+            from.1
+        ))
+    }
+
+    /// The replacement of a command found on line `number` of `file`
+    fn command(&mut self, line: &str, cmd: &TexCommand, file: &Path, number: usize) -> String {
+        let original = &line[cmd.start..cmd.end];
+        match cmd.name {
+            "input" | "include" | "subfile" => {
+                let path = deps::normalize(&deps::with_default_extension(cmd.arg, "tex"));
+                if cmd.name == "include" {
+                    let excluded = self
+                        .include_only
+                        .as_ref()
+                        .is_some_and(|only| !only.contains(&path.with_extension("")));
+                    if excluded {
+                        return "\\clearpage".to_string();
+                    }
+                }
+                match self.inline(&path, (file, number), cmd.name == "subfile") {
+                    Some(body) if cmd.name == "include" => format!("\\clearpage{body}\\clearpage"),
+                    Some(body) => body,
+                    None => replace_arg(line, cmd, &self.prefixed(cmd.arg)),
+                }
+            }
+            // `\include`s are resolved above, and the command is not allowed in the body
+            "includeonly" => {
+                self.include_only = Some(
+                    cmd.arg
+                        .split(',')
+                        .map(|f| deps::normalize(Path::new(f.trim())))
+                        .collect(),
+                );
+                String::new()
+            }
+            "includegraphics" if !self.prefix.as_os_str().is_empty() => {
+                let path = self
+                    .graph
+                    .find_graphic(cmd.arg.trim(), &self.graphics_paths);
+                replace_arg(line, cmd, &self.prefixed(&path.display().to_string()))
+            }
+            "graphicspath" => {
+                let dirs = deps::split_graphics_paths(cmd.arg);
+                self.graphics_paths
+                    .extend(dirs.iter().map(|d| deps::normalize(Path::new(d))));
+                if self.prefix.as_os_str().is_empty() {
+                    return original.to_string();
+                }
+                let arg: String = dirs
+                    .iter()
+                    .map(|d| format!("{{{}}}", self.prefixed(d)))
+                    .collect();
+                replace_arg(line, cmd, &arg)
+            }
+            "bibliography" => match &self.bbl {
+                Some(bbl) => format!(
+                    "%\n%% >>> bibliography\n{}\n%% <<< bibliography, back in {}:{number}\n",
+                    bbl.trim_end(),
+                    file.display()
+                ),
+                None => {
+                    let files: Vec<String> = cmd.arg.split(',').map(|f| self.prefixed(f)).collect();
+                    replace_arg(line, cmd, &files.join(","))
+                }
+            },
+            "addbibresource" => replace_arg(line, cmd, &self.prefixed(cmd.arg)),
+            _ => original.to_string(),
+        }
+    }
+
+    /// Flatten the lines of `file`, the first of which has line number `first`. The contents of
+    /// verbatim environments are kept as is.
+    fn flatten_lines(&mut self, file: &Path, lines: &[&str], first: usize) -> String {
+        let mut out = String::new();
+        let mut verbatim: Option<&str> = None;
+        for (i, line) in lines.iter().enumerate() {
+            if let Some(env) = verbatim {
+                if line.contains(&format!("\\end{{{env}}}")) {
+                    verbatim = None;
+                }
+                out += line;
+                out.push('\n');
+                continue;
+            }
+
+            let code = deps::strip_comments(line, true);
+            verbatim = deps::begins_verbatim(&code);
+            let mut last = 0;
+            let mut replacement = String::new();
+            for cmd in find_commands(&code, COMMANDS) {
+                out += &line[last..cmd.start];
+                replacement = self.command(line, &cmd, file, first + i);
+                out += &replacement;
+                last = cmd.end;
+            }
+            // Inlined files end with a line break, another one would start a new paragraph
+            let rest = &line[last..];
+            out += rest;
+            if !rest.is_empty() || !replacement.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+fn warn(message: &str) {
+    eprintln!(
+        "{}WARNING: {message}{}",
+        Fg(color::Yellow),
+        Fg(color::Reset)
+    );
+}
+
+/// Path leading from `dir` back to the document root
+fn path_to_root(root: &Path, dir: &Path) -> PathBuf {
+    let root = deps::normalize(root);
+    match deps::normalize(dir).strip_prefix(&root) {
+        Ok(rel) => rel.components().map(|_| "..").collect(),
+        Err(_) => root,
+    }
+}
+
+#[test]
+fn test_path_to_root() {
+    let root = Path::new("/doc");
+    assert_eq!(path_to_root(root, Path::new("/doc")), PathBuf::new());
+    assert_eq!(
+        path_to_root(root, Path::new("/doc/out/./arxiv")),
+        PathBuf::from("../..")
+    );
+    assert_eq!(path_to_root(root, Path::new("/tmp")), PathBuf::from("/doc"));
+}
+
+/// Flatten the document starting at `main_file` in `root`, to be written into `output_dir`.
+/// Returns the flattened source and the number of files inlined.
+pub fn flatten_document(
+    root: &Path,
+    main_file: &Path,
+    output_dir: &Path,
+    bbl: Option<String>,
+) -> (String, usize) {
+    let mut flattener = Flattener {
+        graph: DepGraph {
+            root: root.to_path_buf(),
+            main_file: main_file.to_path_buf(),
+            edges: BTreeMap::new(),
+        },
+        prefix: path_to_root(root, output_dir),
+        graphics_paths: vec![PathBuf::new()],
+        include_only: None,
+        bbl,
+        stack: vec![main_file.to_path_buf()],
+        inlined: 0,
+    };
+
+    let source = match fs::read_to_string(root.join(main_file)) {
+        Ok(s) => s,
+        Err(e) => exit_with_error!("Could not read `{}`: {}", main_file.display(), e),
+    };
+    let lines: Vec<&str> = source.lines().collect();
+    let flat = flattener.flatten_lines(main_file, &lines, 1);
+    (flat, flattener.inlined)
+}
+
+pub fn flatten(config: Config, args: FlattenArgs) {
+    let main_file = deps::normalize(&match args.main_file {
+        Some(f) => PathBuf::from(f),
+        None => config.main_file.clone(),
+    });
+
+    if !config.root.join(&main_file).is_file() {
+        exit_with_error!("Cannot find main file `{}`.", main_file.display());
+    }
+
+    let output = match args.output {
+        Some(o) => config.root.join(o),
+        None => {
+            let stem = main_file.with_extension("");
+            config.root.join(format!("{}-flat.tex", stem.display()))
+        }
+    };
+    if deps::normalize(&output) == deps::normalize(&config.root.join(&main_file)) {
+        exit_with_error!(
+            "Refusing to overwrite the main file `{}`.",
+            main_file.display()
+        );
+    }
+
+    let bbl = match args.bbl {
+        true => {
+            let bbl = log::find_log_file(&config, &main_file).with_extension("bbl");
+            match fs::read_to_string(&bbl) {
+                Ok(s) => Some(s),
+                Err(e) => exit_with_error!(
+                    "Could not read bibliography `{}`: {}. Compile the document to create it.",
+                    bbl.display(),
+                    e
+                ),
+            }
+        }
+        false => None,
+    };
+
+    let (flat, inlined) = flatten_document(&config.root, &main_file, utils::parrent(&output), bbl);
+
+    if let Some(dir) = output.parent() {
+        utils::create_dir_all(dir);
+    }
+    utils::write(&output, flat);
+
+    println!(
+        "Inlined {} files into `{}`.",
+        inlined,
+        output
+            .strip_prefix(&config.root)
+            .unwrap_or(&output)
+            .display()
+    );
+}
@@ -12,7 +12,7 @@ use zip_extensions::zip_extract;
 use crate::{
     config::{self, LOCAL_CONFIG_FILE},
     exit_with_error,
-    opts::{Config, ConfigCreateArgs, InitArgs, RemoteTemplate},
+    opts::{CompileArgs, Config, ConfigCreateArgs, InitArgs, RemoteTemplate},
     templates::{self, Template},
     utils,
 };
@@ -74,9 +74,9 @@ pub fn init(cwd: PathBuf, mut config: Config, args: InitArgs) {
 
         let template_path = match args.template {
             Some(t) => match templates::search_templates(&t, &templates) {
-                Some(Template::Local(p)) => config.templates_dir.join(p),
+                Some(Template::Local { dir, path }) => dir.join(path),
                 Some(Template::Remote { name, remote }) => {
-                    config = remote.config.clone();
+                    config.merge(remote.config.clone());
                     clone_remote_template(&config.temp_dir, name, remote)
                 }
                 None => exit_with_error!("Could not find template '{}'.", t),
@@ -98,9 +98,9 @@ pub fn init(cwd: PathBuf, mut config: Config, args: InitArgs) {
 
                 // Run the fuzzy finder
                 match utils::start_fuzzy_finder(items, nr_of_items as i8) {
-                    Some(Template::Local(p)) => config.templates_dir.join(p),
+                    Some(Template::Local { dir, path }) => dir.join(path),
                     Some(Template::Remote { name, remote }) => {
-                        config = remote.config.clone();
+                        config.merge(remote.config.clone());
                         clone_remote_template(&config.temp_dir, name, remote)
                     }
                     None => {
@@ -179,5 +179,5 @@ pub fn init(cwd: PathBuf, mut config: Config, args: InitArgs) {
 
     // Compile document with the new configuration
     let main_file = config.main_file.clone();
-    crate::compile::compile_file(config, main_file);
+    crate::compile::compile_file(config, main_file, &CompileArgs::default());
 }
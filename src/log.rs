@@ -1,14 +1,966 @@
-use std::path::PathBuf;
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 
-use crate::exit_with_error;
+use termion::{
+    color::{self, Fg},
+    style,
+};
 
-pub fn print_log(root: PathBuf, main_file: &PathBuf) {
-    let log_file = root.join(main_file).with_extension("log");
+use crate::{
+    deps::DepGraph,
+    exit_with_error,
+    opts::{Config, Deny, LogArgs, LogFilterArgs, LogLevel},
+    utils,
+};
 
+/// TeX wraps lines in the log file at this many characters
+const MAX_LOG_LINE_LENGTH: usize = 79;
+
+/// Options for the directory the compiler writes output files to
+const OUTPUT_DIR_OPTIONS: &[&str] = &["output-directory", "outdir", "out-dir"];
+
+/// Options for the directory the compiler writes auxiliary files like the log to
+const AUX_DIR_OPTIONS: &[&str] = &["aux-directory", "auxdir", "aux-dir"];
+
+/// Where the compiler writes its output, as given by options to the compilation command
+#[derive(Debug, Default, PartialEq, Eq)]
+struct OutputOptions {
+    jobname: Option<String>,
+    output_dir: Option<PathBuf>,
+    aux_dir: Option<PathBuf>,
+}
+
+impl OutputOptions {
+    /// Read `-jobname`, `-output-directory` and their latexmk and MiKTeX equivalents from a
+    /// compilation command
+    fn from_command(cmd: &str) -> Self {
+        let mut options = Self::default();
+        let mut words = utils::split_command(cmd).into_iter();
+
+        while let Some(word) = words.next() {
+            let option = match word.strip_prefix("--").or_else(|| word.strip_prefix('-')) {
+                Some(o) => o,
+                None => continue,
+            };
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (option, None),
+            };
+
+            let known = name == "jobname"
+                || OUTPUT_DIR_OPTIONS.contains(&name)
+                || AUX_DIR_OPTIONS.contains(&name);
+            if !known {
+                continue;
+            }
+
+            let value = match value.or_else(|| words.next()) {
+                Some(v) => v,
+                None => continue,
+            };
+            if name == "jobname" {
+                options.jobname = Some(value);
+            } else if OUTPUT_DIR_OPTIONS.contains(&name) {
+                options.output_dir = Some(PathBuf::from(value));
+            } else {
+                options.aux_dir = Some(PathBuf::from(value));
+            }
+        }
+
+        options
+    }
+
+    /// Read `$out_dir`, `$aux_dir` and `$jobname` from a latexmk configuration file
+    fn from_latexmkrc(source: &str) -> Self {
+        let mut options = Self::default();
+        for line in source.lines() {
+            let (name, value) = match line.trim().split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim().trim_end_matches(';').trim()),
+                None => continue,
+            };
+            let value = value.trim_matches(['\'', '"']).to_string();
+            match name {
+                "$jobname" => options.jobname = Some(value),
+                "$out_dir" => options.output_dir = Some(PathBuf::from(value)),
+                "$aux_dir" => options.aux_dir = Some(PathBuf::from(value)),
+                _ => {}
+            }
+        }
+        options
+    }
+
+    /// Options in `other` override the ones in `self`
+    fn merge(self, other: Self) -> Self {
+        Self {
+            jobname: other.jobname.or(self.jobname),
+            output_dir: other.output_dir.or(self.output_dir),
+            aux_dir: other.aux_dir.or(self.aux_dir),
+        }
+    }
+}
+
+#[test]
+fn test_output_options() {
+    assert_eq!(
+        OutputOptions::from_command(
+            "cd \"/doc\" && pdflatex -jobname thesis -output-directory=build main.tex"
+        ),
+        OutputOptions {
+            jobname: Some("thesis".to_string()),
+            output_dir: Some(PathBuf::from("build")),
+            aux_dir: None,
+        }
+    );
+    assert_eq!(
+        OutputOptions::from_latexmkrc("$pdf_mode = 1;\n$aux_dir = 'build/aux';\n"),
+        OutputOptions {
+            jobname: None,
+            output_dir: None,
+            aux_dir: Some(PathBuf::from("build/aux")),
+        }
+    );
+}
+
+/// Files named `name` in `dir` and its subdirectories. Hidden directories are skipped.
+fn find_files_named(dir: &Path, name: &OsStr, found: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'));
+        if path.is_dir() && !hidden {
+            find_files_named(&path, name, found);
+        } else if path.file_name() == Some(name) {
+            found.push(path);
+        }
+    }
+}
+
+/// The output options used when compiling `main_file`, read from the compilation command and
+/// latexmk configuration. Returns the options and the job name.
+fn output_options(config: &Config, main_file: &Path) -> (OutputOptions, String) {
+    let cmd = utils::replace_path_placeholders(&config.compile_cmd, main_file, &config.root);
+    let mut options = OutputOptions::default();
+    if cmd.contains("latexmk") {
+        for rc in ["latexmkrc", ".latexmkrc"] {
+            if let Ok(source) = fs::read_to_string(config.root.join(rc)) {
+                options = options.merge(OutputOptions::from_latexmkrc(&source));
+            }
+        }
+    }
+    let options = options.merge(OutputOptions::from_command(&cmd));
+
+    let jobname = match &options.jobname {
+        Some(j) => j.clone(),
+        None => main_file
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    (options, jobname)
+}
+
+/// Directory the build files of `main_file` are put in. Like latexmk does with the log, this is
+/// the auxiliary directory if one is given, and the output directory otherwise.
+pub fn aux_dir(config: &Config, main_file: &Path) -> PathBuf {
+    let (options, _) = output_options(config, main_file);
+    let dir = options.aux_dir.or(options.output_dir).unwrap_or_default();
+    config.root.join(dir)
+}
+
+/// Path of the file `<jobname>.<extension>` in the build directory of `main_file`
+pub fn aux_file(config: &Config, main_file: &Path, extension: &str) -> PathBuf {
+    let (_, jobname) = output_options(config, main_file);
+    aux_dir(config, main_file).join(format!("{jobname}.{extension}"))
+}
+
+/// Find the log file produced when compiling `main_file`. The job name and output directory are
+/// read from the compilation command and latexmk configuration. If there is no log file where
+/// they say, the most recently modified log file with the right name in the document is used.
+pub fn find_log_file(config: &Config, main_file: &Path) -> PathBuf {
+    let log_file = aux_file(config, main_file, "log");
+    let file_name = log_file.file_name().unwrap_or_default().to_os_string();
+    if log_file.is_file() {
+        return log_file;
+    }
+
+    let mut found = vec![];
+    find_files_named(&config.root, &file_name, &mut found);
+    found
+        .into_iter()
+        .max_by_key(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .unwrap_or(log_file)
+}
+
+/// Read a log file and join lines which TeX has wrapped
+pub fn read_log_lines(log_file: &Path) -> Vec<String> {
+    let contents = match fs::read(log_file) {
+        // Logs are not always valid UTF-8, as TeX writes input bytes as is
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(e) => exit_with_error!("Could not read log file `{}`: {}", log_file.display(), e),
+    };
+
+    let mut lines: Vec<String> = vec![];
+    let mut wrapped = false;
+    for line in contents.lines() {
+        match (wrapped, lines.last_mut()) {
+            (true, Some(last)) => last.push_str(line),
+            _ => lines.push(line.to_string()),
+        }
+        wrapped = line.chars().count() == MAX_LOG_LINE_LENGTH;
+    }
+    lines
+}
+
+/// Severity of a diagnostic in the log file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+
+    /// Overfull and underfull boxes
+    BadBox,
+}
+
+/// An error or warning found in a log file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+
+    /// The package or class which emitted the diagnostic
+    pub package: Option<String>,
+
+    /// The file being read when the diagnostic was emitted, as written in the log
+    pub file: Option<PathBuf>,
+
+    /// Line in `file` the diagnostic refers to
+    pub line: Option<usize>,
+
+    /// The part of the line TeX had read when the error occurred
+    pub snippet: Option<String>,
+}
+
+impl Diagnostic {
+    /// Whether the diagnostic is about an undefined reference or citation, or a multiply defined
+    /// label. The summaries LaTeX prints at the end of the run, like `There were undefined
+    /// references`, repeat earlier warnings and are not included.
+    pub fn is_undefined_ref(&self) -> bool {
+        let m = &self.message;
+        ((m.starts_with("Reference `") || m.starts_with("Citation `")) && m.contains("undefined"))
+            || (m.starts_with("Label `") && m.contains("multiply defined"))
+    }
+
+    /// Whether the diagnostic belongs to a denied class
+    pub fn is_denied_by(&self, deny: Deny) -> bool {
+        match deny {
+            Deny::Warnings => self.level == Level::Warning,
+            Deny::UndefinedRefs => self.level == Level::Warning && self.is_undefined_ref(),
+        }
+    }
+}
+
+/// Check whether a token following `(` in the log is the name of a file being opened
+fn is_file_token(token: &str) -> bool {
+    (token.starts_with("./") || token.starts_with("../") || token.starts_with('/'))
+        || Path::new(token).extension().is_some() && !token.ends_with('.')
+}
+
+/// Update the stack of open files with the parentheses on a line of the log
+fn track_files(line: &str, stack: &mut Vec<Option<PathBuf>>) {
+    let mut rest = line;
+    while let Some(i) = rest.find(['(', ')']) {
+        if rest[i..].starts_with(')') {
+            stack.pop();
+            rest = &rest[i + 1..];
+            continue;
+        }
+
+        rest = &rest[i + 1..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+            .unwrap_or(rest.len());
+        let token = &rest[..end];
+        match is_file_token(token) {
+            true => stack.push(Some(PathBuf::from(token))),
+            false => stack.push(None),
+        }
+        rest = &rest[end..];
+    }
+}
+
+/// Parse errors in the `-file-line-error` format: `./file.tex:12: message`. The file can be of any
+/// type, like a package or bibliography.
+pub fn parse_file_line_error(line: &str) -> Option<(PathBuf, usize, String)> {
+    line.match_indices(':').find_map(|(i, _)| {
+        let file = &line[..i];
+        let rest = &line[i + 1..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let message = rest[digits..].strip_prefix(": ")?;
+        match is_file_token(file) && !file.contains(char::is_whitespace) {
+            true => Some((
+                PathBuf::from(file),
+                rest[..digits].parse().ok()?,
+                message.to_string(),
+            )),
+            false => None,
+        }
+    })
+}
+
+#[test]
+fn test_parse_file_line_error() {
+    assert_eq!(
+        parse_file_line_error("./main.tex:12: Undefined control sequence."),
+        Some((
+            PathBuf::from("./main.tex"),
+            12,
+            "Undefined control sequence.".to_string()
+        ))
+    );
+    assert_eq!(
+        parse_file_line_error("/usr/share/texmf/tex/latex/tools/array.sty:3: Missing number."),
+        Some((
+            PathBuf::from("/usr/share/texmf/tex/latex/tools/array.sty"),
+            3,
+            "Missing number.".to_string()
+        ))
+    );
+    assert_eq!(
+        parse_file_line_error("./refs.bbl:7: LaTeX Error: Something's wrong."),
+        Some((
+            PathBuf::from("./refs.bbl"),
+            7,
+            "LaTeX Error: Something's wrong.".to_string()
+        ))
+    );
+    assert_eq!(
+        parse_file_line_error("Package hyperref Warning: 12: x"),
+        None
+    );
+    assert_eq!(parse_file_line_error("Time: 12:30: done"), None);
+}
+
+/// Find the `l.<line> <text>` line following an error
+fn find_error_line(lines: &[String]) -> Option<(usize, String)> {
+    lines.iter().take(20).find_map(|l| {
+        let rest = l.strip_prefix("l.")?;
+        let (number, snippet) = rest.split_once(' ').unwrap_or((rest, ""));
+        Some((number.parse().ok()?, snippet.to_string()))
+    })
+}
+
+/// Find `on input line <n>` in a warning message
+fn find_input_line(message: &str) -> Option<usize> {
+    let (_, rest) = message.rsplit_once("on input line ")?;
+    rest.trim_end_matches('.').trim().parse().ok()
+}
+
+/// Find the package in messages like `Package hyperref Warning: ...`
+fn find_package(message: &str) -> Option<String> {
+    let rest = message
+        .strip_prefix("Package ")
+        .or_else(|| message.strip_prefix("Class "))?;
+    let (name, _) = rest.split_once(' ')?;
+    Some(name.to_string())
+}
+
+/// Find the line of a bad box warning like `Overfull \hbox (1.0pt too wide) in paragraph at
+/// lines 12--13`
+fn find_bad_box_line(message: &str) -> Option<usize> {
+    let (_, rest) = message
+        .rsplit_once(" at lines ")
+        .or_else(|| message.rsplit_once(" at line "))?;
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+/// Parse the errors and warnings in the lines of a log file
+pub fn parse_diagnostics(lines: &[String]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut stack: Vec<Option<PathBuf>> = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let current_file = stack.iter().rev().flatten().next().cloned();
+
+        if let Some(message) = line.strip_prefix("! ") {
+            let error_line = find_error_line(&lines[i + 1..]);
+            diagnostics.push(Diagnostic {
+                level: Level::Error,
+                package: find_package(message),
+                message: message.to_string(),
+                file: current_file,
+                line: error_line.as_ref().map(|(n, _)| *n),
+                snippet: error_line.map(|(_, s)| s),
+            });
+        } else if let Some((file, number, message)) = parse_file_line_error(line) {
+            let snippet = find_error_line(&lines[i + 1..]).map(|(_, s)| s);
+            diagnostics.push(Diagnostic {
+                level: Level::Error,
+                package: find_package(&message),
+                message,
+                file: Some(file),
+                line: Some(number),
+                snippet,
+            });
+        } else if line.starts_with("Overfull \\") || line.starts_with("Underfull \\") {
+            diagnostics.push(Diagnostic {
+                level: Level::BadBox,
+                message: line.to_string(),
+                package: None,
+                file: current_file,
+                line: find_bad_box_line(line),
+                snippet: None,
+            });
+        } else if let Some((prefix, message)) = line.split_once("Warning: ") {
+            // Warnings continue until the next empty line
+            let mut message = message.to_string();
+            for l in lines[i + 1..].iter().take_while(|l| !l.trim().is_empty()) {
+                let l = l.trim_start();
+                // Package warnings indent continuation lines with `(package)`
+                let l = match l.starts_with('(') {
+                    true => l.split_once(')').map(|(_, r)| r.trim_start()).unwrap_or(l),
+                    false => l,
+                };
+                message.push(' ');
+                message.push_str(l);
+            }
+            let line = find_input_line(&message);
+            diagnostics.push(Diagnostic {
+                level: Level::Warning,
+                message,
+                package: find_package(prefix),
+                file: current_file,
+                line,
+                snippet: None,
+            });
+        }
+
+        track_files(line, &mut stack);
+    }
+
+    diagnostics
+}
+
+#[test]
+fn test_parse_diagnostics() {
+    let log = [
+        "(./main.tex",
+        "LaTeX2e <2023-11-01>",
+        "(/usr/share/texmf/tex/latex/base/article.cls",
+        "Document Class: article 2023/05/17 v1.4n Standard LaTeX document class",
+        ")",
+        "(./chapters/intro.tex",
+        "! Undefined control sequence.",
+        "l.3 Some \\foo",
+        "              bar",
+        ")",
+        "",
+        "LaTeX Warning: Reference `fig:x' on page 1 undefined on input line 12.",
+        "",
+        "Overfull \\hbox (15.0pt too wide) in paragraph at lines 14--15",
+        "",
+        "Package hyperref Warning: Token not allowed in a PDF string (Unicode):",
+        "(hyperref)                removing `math shift' on input line 20.",
+        "",
+        ")",
+    ]
+    .map(String::from);
+
+    assert_eq!(
+        parse_diagnostics(&log),
+        vec![
+            Diagnostic {
+                level: Level::Error,
+                message: "Undefined control sequence.".to_string(),
+                package: None,
+                file: Some(PathBuf::from("./chapters/intro.tex")),
+                line: Some(3),
+                snippet: Some("Some \\foo".to_string()),
+            },
+            Diagnostic {
+                level: Level::Warning,
+                message: "Reference `fig:x' on page 1 undefined on input line 12.".to_string(),
+                package: None,
+                file: Some(PathBuf::from("./main.tex")),
+                line: Some(12),
+                snippet: None,
+            },
+            Diagnostic {
+                level: Level::BadBox,
+                message: "Overfull \\hbox (15.0pt too wide) in paragraph at lines 14--15".to_string(),
+                package: None,
+                file: Some(PathBuf::from("./main.tex")),
+                line: Some(14),
+                snippet: None,
+            },
+            Diagnostic {
+                level: Level::Warning,
+                message: "Token not allowed in a PDF string (Unicode): removing `math shift' on input line 20.".to_string(),
+                package: Some("hyperref".to_string()),
+                file: Some(PathBuf::from("./main.tex")),
+                line: Some(20),
+                snippet: None,
+            },
+        ]
+    );
+}
+
+/// Find the source file a diagnostic refers to. Paths in the log are relative to the document
+/// root, except for subfiles compiled from their own directory, which are looked up among the
+/// files included in the document.
+pub fn resolve_source_file(root: &Path, main_file: &Path, file: &Path) -> Option<PathBuf> {
+    let path = root.join(file);
+    if path.is_file() {
+        return Some(path);
+    }
+
+    let file = file.strip_prefix("./").unwrap_or(file);
+    DepGraph::build(root, main_file, None)
+        .files()
+        .into_iter()
+        .map(|d| d.path)
+        .find(|p| p.ends_with(file))
+        .map(|p| root.join(p))
+}
+
+/// Write the lines around `line` in `file`, highlighting the line itself. Nothing is written if
+/// the file cannot be read or does not have the line.
+fn write_source_context(
+    out: &mut impl Write,
+    file: &Path,
+    display: &Path,
+    line: usize,
+    snippet: Option<&str>,
+    n: usize,
+) -> io::Result<()> {
+    let source = match fs::read_to_string(file) {
+        Ok(s) => s,
+        Err(_) => return Ok(()),
+    };
+    let lines: Vec<&str> = source.lines().collect();
+    if line == 0 || line > lines.len() {
+        return Ok(());
+    }
+
+    let first = line.saturating_sub(n).max(1);
+    let last = (line + n).min(lines.len());
+    let width = last.to_string().len();
+    let gutter = |s: &str| format!("{}{:>width$} |{}", Fg(color::Blue), s, Fg(color::Reset));
+
+    writeln!(
+        out,
+        "{}{:>width$}--> {}:{}{}",
+        Fg(color::Blue),
+        "",
+        display.display(),
+        line,
+        Fg(color::Reset)
+    )?;
+    writeln!(out, "{}", gutter(""))?;
+    for (i, text) in lines.iter().enumerate().take(last).skip(first - 1) {
+        let number = i + 1;
+        if number != line {
+            writeln!(out, "{} {}", gutter(&number.to_string()), text)?;
+            continue;
+        }
+
+        writeln!(
+            out,
+            "{} {}{}{}{}",
+            gutter(&number.to_string()),
+            style::Bold,
+            text,
+            style::Reset,
+            Fg(color::Reset)
+        )?;
+
+        // Point at where TeX stopped reading the line
+        let column = snippet
+            .filter(|s| !s.is_empty() && text.starts_with(s.trim_end()))
+            .map(|s| s.trim_end().chars().count());
+        if let Some(column) = column {
+            writeln!(
+                out,
+                "{} {}{}^{}",
+                gutter(""),
+                " ".repeat(column.saturating_sub(1)),
+                Fg(color::Red),
+                Fg(color::Reset)
+            )?;
+        }
+    }
+    writeln!(out, "{}", gutter(""))
+}
+
+#[test]
+fn test_write_source_context() {
+    let dir = std::env::temp_dir().join("blatex-test-source-context");
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("main.tex");
+    let source: String = (1..=12).map(|i| format!("line {i}\n")).collect();
+    fs::write(&file, source).unwrap();
+
+    let render = |file: &Path, line: usize, snippet: Option<&str>, n: usize| {
+        let mut out = vec![];
+        write_source_context(&mut out, file, Path::new("main.tex"), line, snippet, n).unwrap();
+        // Leave out colors and styles
+        let text = String::from_utf8(out).unwrap();
+        let mut plain = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => {
+                    chars.find(|c| c.is_ascii_alphabetic());
+                }
+                c => plain.push(c),
+            }
+        }
+        plain
+    };
+
+    // The gutter is as wide as the largest line number
+    assert_eq!(
+        render(&file, 10, Some("line 1"), 1),
+        "  --> main.tex:10\n   |\n 9 | line 9\n10 | line 10\n   |      ^\n11 | line 11\n   |\n"
+    );
+
+    // The context is clamped to the start and end of the file
+    assert_eq!(
+        render(&file, 1, None, 2),
+        " --> main.tex:1\n  |\n1 | line 1\n2 | line 2\n3 | line 3\n  |\n"
+    );
+    assert!(render(&file, 12, None, 3).ends_with("11 | line 11\n12 | line 12\n   |\n"));
+
+    // Nothing is shown for lines and files which do not exist
+    assert_eq!(render(&file, 13, None, 1), "");
+    assert_eq!(render(&dir.join("missing.tex"), 1, None, 1), "");
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Which diagnostics to show, from the configuration and command line
+pub struct LogFilter {
+    level: LogLevel,
+    ignore: Vec<String>,
+    ignore_packages: Vec<String>,
+}
+
+impl LogFilter {
+    /// Options on the command line are added to the ones in the configuration. The level given
+    /// on the command line overrides the configured one.
+    pub fn new(config: &Config, args: &LogFilterArgs) -> Self {
+        Self {
+            level: args.only.unwrap_or(config.log_level),
+            ignore: [config.log_ignore.clone(), args.ignore.clone()].concat(),
+            ignore_packages: [
+                config.log_ignore_packages.clone(),
+                args.ignore_package.clone(),
+            ]
+            .concat(),
+        }
+    }
+
+    pub fn shows(&self, d: &Diagnostic) -> bool {
+        let level_shown = match d.level {
+            Level::Error => true,
+            Level::Warning => self.level >= LogLevel::Warnings,
+            Level::BadBox => self.level >= LogLevel::All,
+        };
+        let ignored_package = d
+            .package
+            .as_ref()
+            .is_some_and(|p| self.ignore_packages.contains(p));
+        let ignored_message = self.ignore.iter().any(|i| d.message.contains(i));
+
+        level_shown && !ignored_package && !ignored_message
+    }
+}
+
+#[test]
+fn test_log_filter() {
+    let diagnostic = |level, message: &str, package: Option<&str>| Diagnostic {
+        level,
+        message: message.to_string(),
+        package: package.map(String::from),
+        file: None,
+        line: None,
+        snippet: None,
+    };
+    let error = diagnostic(Level::Error, "Undefined control sequence.", None);
+    let warning = diagnostic(Level::Warning, "Token not allowed", Some("hyperref"));
+    let bad_box = diagnostic(Level::BadBox, "Overfull \\hbox (1.0pt too wide)", None);
+
+    let mut config = Config::default();
+    let filter = LogFilter::new(&config, &LogFilterArgs::default());
+    assert!(filter.shows(&error));
+    assert!(filter.shows(&warning));
+    assert!(filter.shows(&bad_box));
+
+    config.log_ignore_packages = vec!["hyperref".to_string()];
+    let args = LogFilterArgs {
+        ignore: vec!["Overfull \\hbox".to_string()],
+        ..Default::default()
+    };
+    let filter = LogFilter::new(&config, &args);
+    assert!(filter.shows(&error));
+    assert!(!filter.shows(&warning));
+    assert!(!filter.shows(&bad_box));
+
+    let args = LogFilterArgs {
+        only: Some(LogLevel::Errors),
+        ..Default::default()
+    };
+    let filter = LogFilter::new(&Config::default(), &args);
+    assert!(filter.shows(&error));
+    assert!(!filter.shows(&diagnostic(Level::Warning, "Citation undefined", None)));
+}
+
+#[test]
+fn test_denied_diagnostics() {
+    let warning = |message: &str| Diagnostic {
+        level: Level::Warning,
+        message: message.to_string(),
+        package: None,
+        file: None,
+        line: None,
+        snippet: None,
+    };
+
+    let reference = warning("Reference `fig:x' on page 1 undefined on input line 12.");
+    let citation = warning("Citation `knuth84' on page 2 undefined on input line 3.");
+    let label = warning("Label `sec:intro' multiply defined.");
+    let font = warning("Font shape `OT1/cmr/m/scit' undefined");
+
+    for d in [&reference, &citation, &label] {
+        assert!(d.is_denied_by(Deny::UndefinedRefs));
+        assert!(d.is_denied_by(Deny::Warnings));
+    }
+    assert!(!font.is_denied_by(Deny::UndefinedRefs));
+    assert!(font.is_denied_by(Deny::Warnings));
+
+    // The summary at the end of the run repeats the warnings above
+    let summary = warning("There were undefined references.");
+    assert!(!summary.is_undefined_ref());
+    assert!(summary.is_denied_by(Deny::Warnings));
+}
+
+/// Print diagnostics. With `context`, that many lines of source code around each of them are
+/// shown as well.
+fn print_diagnostics(
+    root: &Path,
+    main_file: &Path,
+    diagnostics: &[&Diagnostic],
+    context: Option<usize>,
+) {
+    for d in diagnostics {
+        let (color, label): (&dyn color::Color, &str) = match d.level {
+            Level::Error => (&color::Red, "error"),
+            Level::Warning => (&color::Yellow, "warning"),
+            Level::BadBox => (&color::LightBlack, "bad box"),
+        };
+        println!(
+            "{}{}{}:{} {}{}",
+            style::Bold,
+            Fg(color),
+            label,
+            Fg(color::Reset),
+            d.message,
+            style::Reset
+        );
+
+        if let (Some(file), Some(line)) = (&d.file, d.line) {
+            let path = resolve_source_file(root, main_file, file);
+            match (path, context) {
+                (Some(path), Some(n)) => {
+                    let display = path.strip_prefix(root).unwrap_or(&path);
+                    let snippet = d.snippet.as_deref();
+                    // Failing to write to stdout is not worth stopping for
+                    let _ =
+                        write_source_context(&mut io::stdout(), &path, display, line, snippet, n);
+                }
+                _ => println!(
+                    "{} --> {}:{}{}",
+                    Fg(color::Blue),
+                    file.strip_prefix("./").unwrap_or(file).display(),
+                    line,
+                    Fg(color::Reset)
+                ),
+            }
+        }
+        println!();
+    }
+}
+
+/// Pluralize a count of something, like `1 error` or `2 errors`
+fn count(n: usize, singular: &str, plural: &str) -> String {
+    match n {
+        1 => format!("1 {singular}"),
+        _ => format!("{n} {plural}"),
+    }
+}
+
+/// Print the number of diagnostics of each level
+fn print_summary(shown: &[&Diagnostic], hidden: usize) {
+    let n = |level| shown.iter().filter(|d| d.level == level).count();
+    let mut summary = [
+        count(n(Level::Error), "error", "errors"),
+        count(n(Level::Warning), "warning", "warnings"),
+        count(n(Level::BadBox), "bad box", "bad boxes"),
+    ]
+    .join(", ");
+    if hidden > 0 {
+        summary.push_str(&format!(" ({hidden} hidden)"));
+    }
+
+    let color: &dyn color::Color = match (n(Level::Error), n(Level::Warning)) {
+        (0, 0) => &color::Green,
+        (0, _) => &color::Yellow,
+        _ => &color::Red,
+    };
+    println!("{}{}{}", Fg(color), summary, Fg(color::Reset));
+}
+
+/// Read the output file and page count from `Output written on main.pdf (12 pages, 1234 bytes).`
+pub fn find_output(log_lines: &[String]) -> Option<(PathBuf, usize)> {
+    log_lines.iter().rev().find_map(|l| {
+        let rest = l.strip_prefix("Output written on ")?;
+        let (file, rest) = rest.rsplit_once(" (")?;
+        let (pages, _) = rest.split_once(' ')?;
+        Some((PathBuf::from(file), pages.parse().ok()?))
+    })
+}
+
+#[test]
+fn test_find_output() {
+    let log = ["Output written on build/main.pdf (12 pages, 123456 bytes)."].map(String::from);
+    assert_eq!(
+        find_output(&log),
+        Some((PathBuf::from("build/main.pdf"), 12))
+    );
+}
+
+/// Find the PDF produced when compiling `main_file`. The path is read from the log file if
+/// possible.
+pub fn find_pdf_file(config: &Config, main_file: &Path) -> PathBuf {
+    let log_file = find_log_file(config, main_file);
+    if log_file.is_file() {
+        // The path is relative to the directory the compiler was run in
+        if let Some((file, _)) = find_output(&read_log_lines(&log_file)) {
+            return config.root.join(file);
+        }
+    }
+
+    let (options, jobname) = output_options(config, main_file);
+    config
+        .root
+        .join(options.output_dir.unwrap_or_default())
+        .join(format!("{jobname}.pdf"))
+}
+
+/// Print the errors and warnings in `log_file` which pass the filter, followed by a summary.
+/// With `context`, the source lines around each diagnostic are shown as well.
+pub fn print_log(
+    config: &Config,
+    log_file: &Path,
+    main_file: &Path,
+    context: Option<usize>,
+    filter: &LogFilter,
+) {
     if !log_file.is_file() {
         exit_with_error!("Cannot find log file `{}`.", log_file.display());
     }
 
-    let log = texlog::log::Log::from_path(log_file);
-    log.print_diagnostics();
+    let diagnostics = parse_diagnostics(&read_log_lines(log_file));
+    let shown: Vec<&Diagnostic> = diagnostics.iter().filter(|d| filter.shows(d)).collect();
+
+    print_diagnostics(&config.root, main_file, &shown, context);
+
+    print_summary(&shown, diagnostics.len() - shown.len());
+}
+
+pub fn log(config: Config, cwd: &Path, args: LogArgs) {
+    let filter = LogFilter::new(&config, &args.filter);
+
+    // The argument is either a log file relative to the current directory or the document root,
+    // or a main file
+    let (main_file, log_file) = match args.log_file {
+        Some(f) if f.ends_with(".log") => {
+            let log_file = match cwd.join(&f).is_file() {
+                true => cwd.join(&f),
+                false => config.root.join(&f),
+            };
+            (config.main_file.clone(), log_file)
+        }
+        Some(f) => {
+            let main_file = PathBuf::from(f);
+            let log_file = find_log_file(&config, &main_file);
+            (main_file, log_file)
+        }
+        None => (
+            config.main_file.clone(),
+            find_log_file(&config, &config.main_file),
+        ),
+    };
+
+    println!(
+        "{}Reading log file `{}`.{}\n",
+        Fg(color::LightBlack),
+        log_file
+            .strip_prefix(&config.root)
+            .unwrap_or(&log_file)
+            .display(),
+        Fg(color::Reset)
+    );
+    print_log(&config, &log_file, &main_file, args.context, &filter);
+}
+
+/// Exit with an error if the log of `main_file` contains diagnostics of a denied class
+pub fn check_denied(config: &Config, main_file: &Path, deny: &[Deny]) {
+    if deny.is_empty() {
+        return;
+    }
+
+    let log_file = find_log_file(config, main_file);
+    if !log_file.is_file() {
+        exit_with_error!("Cannot find log file `{}`.", log_file.display());
+    }
+    let diagnostics = parse_diagnostics(&read_log_lines(&log_file));
+
+    let mut denied = false;
+    for class in deny {
+        let n = diagnostics
+            .iter()
+            .filter(|d| d.is_denied_by(*class))
+            .count();
+        if n == 0 {
+            continue;
+        }
+        denied = true;
+        let name = match class {
+            Deny::Warnings => count(n, "warning", "warnings"),
+            Deny::UndefinedRefs => count(
+                n,
+                "undefined reference or label",
+                "undefined references or labels",
+            ),
+        };
+        println!("{}Denied {}{}", Fg(color::Red), name, Fg(color::Reset));
+    }
+
+    if denied {
+        exit_with_error!("Compilation failed because of denied diagnostics.");
+    }
 }
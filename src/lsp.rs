@@ -0,0 +1,433 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    deps,
+    log::{self, Diagnostic, Level, LogFilter},
+    opts::{Config, LogFilterArgs},
+    synctex::{self, SyncTex},
+};
+
+/// Commands the client can run with `workspace/executeCommand`
+const BUILD_COMMAND: &str = "blatex.build";
+const CLEAN_COMMAND: &str = "blatex.clean";
+const FORWARD_SEARCH_COMMAND: &str = "blatex.forwardSearch";
+
+/// JSON-RPC error code for unknown methods
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Read a message framed by a `Content-Length` header. Returns `None` when the input ends.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = match length {
+        Some(l) => l,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing Content-Length",
+            ))
+        }
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[test]
+fn test_read_message() {
+    let body = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+    let input = format!(
+        "Content-Length: {}\r\nContent-Type: application/vscode-jsonrpc\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let mut input = io::Cursor::new(input);
+    assert_eq!(
+        read_message(&mut input).unwrap(),
+        Some(json!({"jsonrpc": "2.0", "method": "exit"}))
+    );
+    assert_eq!(read_message(&mut input).unwrap(), None);
+
+    let mut output = vec![];
+    write_message(&mut output, &json!(null)).unwrap();
+    assert_eq!(output, b"Content-Length: 4\r\n\r\nnull");
+}
+
+/// Convert a `file://` uri to a path, decoding percent-encoded characters
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = vec![];
+    let mut i = 0;
+    while i < path.len() {
+        let decoded = match path[i] {
+            b'%' => path
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()),
+            _ => None,
+        };
+        match decoded {
+            Some(b) => {
+                bytes.push(b);
+                i += 3;
+            }
+            None => {
+                bytes.push(path[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).to_string()))
+}
+
+/// Convert an absolute path to a `file://` uri
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(b as char)
+            }
+            b => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+#[test]
+fn test_uri_conversion() {
+    let path = Path::new("/home/user/my thesis/main.tex");
+    let uri = path_to_uri(path);
+    assert_eq!(uri, "file:///home/user/my%20thesis/main.tex");
+    assert_eq!(uri_to_path(&uri).unwrap(), path);
+    assert_eq!(uri_to_path("untitled:Untitled-1"), None);
+}
+
+/// LSP severity of a diagnostic
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 1,
+        Level::Warning => 2,
+        Level::BadBox => 3,
+    }
+}
+
+struct Server {
+    /// Configuration of the workspace opened by the client, used for files outside documents
+    /// with a local configuration
+    workspace_config: Config,
+
+    /// Uris diagnostics were published for by the last build
+    published: HashSet<String>,
+
+    shutdown: bool,
+}
+
+impl Server {
+    /// Configuration of the document a file belongs to. The document root is found from the
+    /// directory of the file, such that files in subfolders work as well.
+    fn config_for(&self, file: Option<&Path>) -> Result<Config, String> {
+        let dir = match file.and_then(|f| f.parent()) {
+            Some(d) => d.to_path_buf(),
+            None => return Ok(self.workspace_config.clone()),
+        };
+        match Config::find_local_config(&dir) {
+            Some(_) => Config::try_new_local(&dir, None),
+            None => Ok(self.workspace_config.clone()),
+        }
+    }
+
+    /// Run a blatex command in the root of a document. Blatex is run in a separate process, such
+    /// that its output does not end up in the messages to the client.
+    fn run_blatex(&self, config: &Config, args: &[&str]) -> (bool, String) {
+        let exe = match std::env::current_exe() {
+            Ok(e) => e,
+            Err(e) => {
+                return (
+                    false,
+                    format!("Could not find the blatex executable: {}", e),
+                )
+            }
+        };
+        let output = Command::new(exe)
+            .args(args)
+            .current_dir(&config.root)
+            .stdin(Stdio::null())
+            .output();
+        match output {
+            Ok(o) => {
+                let mut text = String::from_utf8_lossy(&o.stdout).to_string();
+                text.push_str(&String::from_utf8_lossy(&o.stderr));
+                (o.status.success(), text)
+            }
+            Err(e) => (false, format!("Could not run blatex: {}", e)),
+        }
+    }
+
+    /// Group the diagnostics of the last build by the uri of the file they refer to.
+    /// Diagnostics which cannot be placed in a source file are shown at the top of the main file.
+    fn diagnostics_by_uri(&self, config: &Config) -> BTreeMap<String, Vec<Value>> {
+        let main_file = config.main_file.clone();
+        let log_file = log::find_log_file(config, &main_file);
+        let mut by_uri: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+        if !log_file.is_file() {
+            return by_uri;
+        }
+
+        let filter = LogFilter::new(config, &LogFilterArgs::default());
+        let diagnostics: Vec<Diagnostic> = log::parse_diagnostics(&log::read_log_lines(&log_file))
+            .into_iter()
+            .filter(|d| filter.shows(d))
+            .collect();
+
+        for d in diagnostics {
+            let file = d
+                .file
+                .as_ref()
+                .and_then(|f| log::resolve_source_file(&config.root, &main_file, f));
+            let (file, line) = match (file, d.line) {
+                (Some(f), Some(l)) => (f, l.saturating_sub(1)),
+                _ => (config.root.join(&main_file), 0),
+            };
+
+            by_uri
+                .entry(path_to_uri(&deps::normalize(&file)))
+                .or_default()
+                .push(json!({
+                    "range": {
+                        "start": {"line": line, "character": 0},
+                        "end": {"line": line + 1, "character": 0},
+                    },
+                    "severity": severity(d.level),
+                    "source": d.package.as_deref().unwrap_or("blatex"),
+                    "message": d.message,
+                }));
+        }
+        by_uri
+    }
+
+    /// Compile the document and publish the diagnostics from its log
+    fn build(&mut self, output: &mut impl Write, file: Option<&Path>) -> io::Result<()> {
+        let config = match self.config_for(file) {
+            Ok(c) => c,
+            Err(e) => return show_error(output, e),
+        };
+        let (success, text) = self.run_blatex(&config, &["compile", "--quiet"]);
+        if !success {
+            notify(
+                output,
+                "window/logMessage",
+                json!({"type": 1, "message": text}),
+            )?;
+        }
+
+        let diagnostics = self.diagnostics_by_uri(&config);
+
+        // Clear diagnostics of files which no longer have any
+        for uri in self.published.drain() {
+            if !diagnostics.contains_key(&uri) {
+                let params = json!({"uri": uri, "diagnostics": []});
+                notify(output, "textDocument/publishDiagnostics", params)?;
+            }
+        }
+        for (uri, diagnostics) in diagnostics {
+            let params = json!({"uri": uri, "diagnostics": diagnostics});
+            notify(output, "textDocument/publishDiagnostics", params)?;
+            self.published.insert(uri);
+        }
+        Ok(())
+    }
+
+    fn clean(&self, output: &mut impl Write, file: Option<&Path>) -> io::Result<()> {
+        let config = match self.config_for(file) {
+            Ok(c) => c,
+            Err(e) => return show_error(output, e),
+        };
+        let (success, text) = self.run_blatex(&config, &["clean"]);
+        if !success {
+            show_error(output, text)?;
+        }
+        Ok(())
+    }
+
+    /// Find the position in the PDF typeset from a line of a file. `line` starts from 0 like
+    /// other LSP positions.
+    fn forward_search(
+        &self,
+        output: &mut impl Write,
+        file: &Path,
+        line: usize,
+    ) -> io::Result<Value> {
+        let config = match self.config_for(Some(file)) {
+            Ok(c) => c,
+            Err(e) => return show_error(output, e).map(|_| Value::Null),
+        };
+        let synctex_file = synctex::find_synctex_file(&config, &config.main_file);
+        if !synctex_file.is_file() {
+            return Ok(Value::Null);
+        }
+        let synctex = match SyncTex::try_read(&synctex_file) {
+            Ok(s) => s,
+            Err(e) => return show_error(output, e).map(|_| Value::Null),
+        };
+        Ok(match synctex.forward(&config.root, file, line + 1) {
+            Some(p) => json!({"page": p.page, "x": p.x, "y": p.y}),
+            None => Value::Null,
+        })
+    }
+
+    fn execute_command(&mut self, output: &mut impl Write, params: &Value) -> io::Result<Value> {
+        let args = params["arguments"].as_array().cloned().unwrap_or_default();
+        let file = args.first().and_then(|a| a.as_str()).and_then(uri_to_path);
+
+        match params["command"].as_str() {
+            Some(BUILD_COMMAND) => self.build(output, file.as_deref())?,
+            Some(CLEAN_COMMAND) => self.clean(output, file.as_deref())?,
+            Some(FORWARD_SEARCH_COMMAND) => {
+                let line = args.get(1).and_then(|l| l.as_u64());
+                if let (Some(file), Some(line)) = (file, line) {
+                    return self.forward_search(output, &file, line as usize);
+                }
+            }
+            _ => {}
+        }
+        Ok(Value::Null)
+    }
+
+    /// Handle a message from the client. Returns the exit code when the client asks the server
+    /// to exit.
+    fn handle(&mut self, output: &mut impl Write, message: Value) -> io::Result<Option<i32>> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let file = params["textDocument"]["uri"].as_str().and_then(uri_to_path);
+
+        let result = match method {
+            "initialize" => {
+                let root = params["rootUri"]
+                    .as_str()
+                    .and_then(uri_to_path)
+                    .or_else(|| params["rootPath"].as_str().map(PathBuf::from));
+                if let Some(root) = root {
+                    match Config::try_new_local(&root, None) {
+                        Ok(config) => self.workspace_config = config,
+                        Err(e) => show_error(output, e)?,
+                    }
+                    if Config::find_local_config(&root).is_none() {
+                        self.workspace_config.root = root;
+                    }
+                }
+                json!({
+                    "capabilities": {
+                        "textDocumentSync": {"openClose": true, "change": 0, "save": true},
+                        "executeCommandProvider": {
+                            "commands": [BUILD_COMMAND, CLEAN_COMMAND, FORWARD_SEARCH_COMMAND],
+                        },
+                    },
+                    "serverInfo": {"name": "blatex", "version": env!("CARGO_PKG_VERSION")},
+                })
+            }
+            "textDocument/didSave" => {
+                self.build(output, file.as_deref())?;
+                return Ok(None);
+            }
+            "workspace/executeCommand" => self.execute_command(output, params)?,
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "exit" => return Ok(Some(if self.shutdown { 0 } else { 1 })),
+            _ => {
+                // Unknown notifications are ignored
+                if let Some(id) = message.get("id") {
+                    let error = json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": METHOD_NOT_FOUND, "message": format!("Unknown method `{method}`")},
+                    });
+                    write_message(output, &error)?;
+                }
+                return Ok(None);
+            }
+        };
+
+        if let Some(id) = message.get("id") {
+            let response = json!({"jsonrpc": "2.0", "id": id, "result": result});
+            write_message(output, &response)?;
+        }
+        Ok(None)
+    }
+}
+
+fn notify(output: &mut impl Write, method: &str, params: Value) -> io::Result<()> {
+    let message = json!({"jsonrpc": "2.0", "method": method, "params": params});
+    write_message(output, &message)
+}
+
+/// Show an error to the user of the client
+fn show_error(output: &mut impl Write, message: String) -> io::Result<()> {
+    notify(
+        output,
+        "window/showMessage",
+        json!({"type": 1, "message": message}),
+    )
+}
+
+/// Run a language server on stdin and stdout
+pub fn lsp(config: Config) {
+    let mut server = Server {
+        workspace_config: config,
+        published: HashSet::new(),
+        shutdown: false,
+    };
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut output = io::stdout();
+
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(m)) => m,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Could not read message: {}", e);
+                continue;
+            }
+        };
+        match server.handle(&mut output, message) {
+            Ok(Some(code)) => std::process::exit(code),
+            Ok(None) => {}
+            Err(e) => {
+                // The client can no longer be reached through stdout
+                eprintln!("Could not write message: {}", e);
+                std::process::exit(1)
+            }
+        }
+    }
+}
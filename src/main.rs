@@ -1,11 +1,26 @@
+mod archive;
+mod arxiv;
 mod clean;
 mod compile;
+mod completions;
 mod config;
+mod deps;
+mod diff;
+mod flatten;
 mod init;
 mod log;
+mod lsp;
+mod man;
+mod manifest;
+mod meta;
 mod opts;
+mod packages;
+mod scaffold;
+mod stats;
+mod synctex;
 mod templates;
 mod utils;
+mod viewer;
 
 use std::path::PathBuf;
 
@@ -24,26 +39,36 @@ fn run(opts: Opts) {
         Command::Init(args) => init::init(opts.cwd, opts.config, args),
         Command::Compile(args) => compile::compile(opts.config, args),
         Command::Clean(args) => clean::clean(opts.config, args),
-        Command::Log(args) => log::print_log(
-            opts.config.root,
-            &match &args.log_file {
-                Some(s) => PathBuf::from(s),
-                None => opts.config.main_file,
-            },
-        ),
+        Command::Log(args) => log::log(opts.config, &opts.cwd, args),
+        Command::View(args) => viewer::view(opts.config, args),
+        Command::Synctex(args) => synctex::synctex(opts.config, args),
+        Command::Lsp => lsp::lsp(opts.config),
+        Command::Completions(args) => completions::completions(args),
+        Command::Man(args) => man::man(args),
+        Command::Deps(args) => deps::deps(opts.config, args),
+        Command::Archive(args) => archive::archive(opts.config, args),
+        Command::Arxiv(args) => arxiv::arxiv(opts.config, args),
+        Command::Flatten(args) => flatten::flatten(opts.config, args),
+        Command::Diff(args) => diff::diff(opts.config, args),
         Command::Add(args) => templates::add_paths(opts.cwd, opts.config, args),
+        Command::AddFile(args) => scaffold::add_file(opts.config, args),
         Command::Template(args) => match args.template_command {
             opts::TemplateCommand::Add(args) => templates::add_paths(opts.cwd, opts.config, args),
             opts::TemplateCommand::AddRepo(args) => {
                 templates::add_repo(opts.cwd, opts.config, args)
             }
-            opts::TemplateCommand::List => templates::list_templates(opts.config),
+            opts::TemplateCommand::List(args) => templates::list_templates(opts.config, args),
         },
         Command::Config(args) => match &args.config_command {
             opts::ConfigCommand::Create(create_args) => {
                 config::create(&opts.cwd, args.global, create_args, &opts.config)
             }
             opts::ConfigCommand::Show => config::show(opts.config, args.global),
+            opts::ConfigCommand::Check => config::check(
+                &opts.cwd,
+                args.global,
+                opts.args.config_path.as_ref().map(PathBuf::from),
+            ),
         },
     }
 }
@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use clap::{Command, CommandFactory};
+
+use crate::{
+    config::{CONFIG_FIELDS, LOCAL_CONFIG_FILE},
+    opts::{Args, Config, ManArgs, PartialConfig},
+    utils,
+};
+
+/// Fields whose default value is the same on every machine, and is shown in `blatex-config(5)`
+const DOCUMENTED_DEFAULTS: &[&str] = &["main_file", "compile_cmd", "clean_cmd", "log_level"];
+
+/// Escape text for use in a roff document
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e")
+        .replace('-', "\\-")
+        .split('\n')
+        .map(|l| match l.starts_with(['.', '\'']) {
+            true => format!("\\&{l}"),
+            false => l.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape(".blatex.toml"), "\\&.blatex.toml");
+    assert_eq!(escape("latexmk -pdf"), "latexmk \\-pdf");
+    assert_eq!(escape("\\input"), "\\einput");
+    assert_eq!(escape("a\n.b"), "a\n\\&.b");
+}
+
+/// Render the man page of a command and of each of its subcommands
+fn command_pages(cmd: &Command, pages: &mut Vec<(String, Vec<u8>)>) {
+    let name = cmd.get_display_name().unwrap_or(cmd.get_name()).to_string();
+    let mut buffer: Vec<u8> = Default::default();
+    clap_mangen::Man::new(cmd.clone())
+        .render(&mut buffer)
+        .expect("rendering into a buffer should not fail");
+    pages.push((format!("{name}.1"), buffer));
+
+    for sub in cmd.get_subcommands().filter(|s| !s.is_hide_set()) {
+        command_pages(sub, pages);
+    }
+}
+
+/// Render `blatex-config(5)`, documenting every field of the configuration files
+fn config_page() -> Vec<u8> {
+    let defaults = toml::Value::try_from(PartialConfig::from(&Config::default()))
+        .expect("the default configuration should be serializable");
+
+    let mut page = format!(
+        ".TH BLATEX-CONFIG 5 \"\" \"blatex {}\"\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    page += ".SH NAME\nblatex\\-config \\- configuration files of blatex\n";
+    page += &format!(
+        ".SH SYNOPSIS\n\\fI{}\\fR in the document root, and \\fIblatex.toml\\fR in the configuration directory\n",
+        escape(LOCAL_CONFIG_FILE)
+    );
+    page += ".SH DESCRIPTION\n";
+    page += &escape(&format!(
+        "Configuration files are TOML files. The local configuration file `{LOCAL_CONFIG_FILE}` is \
+         searched for in the current directory and its parents, and the directory containing it \
+         is the root of the document. Options in the local configuration override the ones in \
+         the global configuration, while lists are extended."
+    ));
+    page += "\n.SH OPTIONS\n";
+    for (name, kind, description) in CONFIG_FIELDS {
+        page += &format!(
+            ".TP\n\\fB{}\\fR (\\fI{}\\fR)\n",
+            escape(name),
+            escape(&kind.describe())
+        );
+        page += &escape(description);
+        page += "\n";
+        let default = match DOCUMENTED_DEFAULTS.contains(name) {
+            true => defaults.get(name),
+            false => None,
+        };
+        if let Some(default) = default {
+            page += &format!(".br\nDefault: {}\n", escape(&default.to_string()));
+        }
+    }
+
+    page += ".SH REMOTE TEMPLATES\n";
+    page += &escape(
+        "Each entry of the `remote_templates` table names a template. An entry is either the url \
+         of a git repository, or a table with the following fields. The table may also set any \
+         other option except `remote_templates`, which is used for documents initialized from \
+         the template.",
+    );
+    page += "\n";
+    for (name, description) in [
+        ("repo", "Url of the git repository containing the template."),
+        (
+            "path",
+            "Path to the template file or directory within the repository. Defaults to the whole repository.",
+        ),
+        ("branch", "Branch to clone. Defaults to the default branch of the repository."),
+    ] {
+        page += &format!(".TP\n\\fB{name}\\fR\n{}\n", escape(description));
+    }
+    page += ".SH EXAMPLE\n.nf\n";
+    page += &escape(
+        "main_file = \"thesis.tex\"\n\
+         compile_cmd = \"latexmk -pdf <main-file>\"\n\
+         \n\
+         [remote_templates]\n\
+         article = \"https://github.com/user/article-template\"\n\
+         slides = { repo = \"https://github.com/user/templates\", path = \"slides\", main_file = \"slides.tex\" }\n",
+    );
+    page += ".fi\n.SH SEE ALSO\nblatex(1), blatex\\-config(1)\n";
+
+    page.into_bytes()
+}
+
+/// Render every man page of blatex, as pairs of file name and contents
+pub fn man_pages() -> Vec<(String, Vec<u8>)> {
+    let mut cmd = Args::command().disable_help_subcommand(true);
+    cmd.build();
+
+    let mut pages = vec![];
+    command_pages(&cmd, &mut pages);
+    pages.push(("blatex-config.5".to_string(), config_page()));
+    pages
+}
+
+#[test]
+fn test_man_pages() {
+    let pages = man_pages();
+    let names: Vec<&str> = pages.iter().map(|(n, _)| n.as_str()).collect();
+    for name in [
+        "blatex.1",
+        "blatex-init.1",
+        "blatex-compile.1",
+        "blatex-template-add-repo.1",
+        "blatex-config-create.1",
+        "blatex-config.5",
+    ] {
+        assert!(names.contains(&name), "missing man page {name}");
+    }
+
+    let config_page = String::from_utf8(config_page()).unwrap();
+    for (name, _, _) in CONFIG_FIELDS {
+        assert!(config_page.contains(&format!("\\fB{}\\fR", escape(name))));
+    }
+}
+
+/// Write every man page into `dir`
+pub fn write_man_pages(dir: &Path) {
+    utils::create_dir_all(dir);
+    for (name, page) in man_pages() {
+        utils::write(&dir.join(name), page);
+    }
+}
+
+pub fn man(args: ManArgs) {
+    let dir = Path::new(&args.out_dir);
+    write_man_pages(dir);
+    println!("Wrote man pages to `{}`.", dir.display());
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{deps::DepGraph, log, opts::Config, utils};
+
+/// Record of the inputs to the last successful build. Used to skip compilation when nothing has
+/// changed.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildManifest {
+    /// The full compilation command
+    pub command: String,
+
+    /// Hashes of every input file, by path relative to the document root
+    pub inputs: BTreeMap<PathBuf, String>,
+}
+
+/// Hash of a file's contents, or `missing` if it cannot be read
+fn hash_file(path: &Path) -> String {
+    match fs::read(path) {
+        Ok(bytes) => format!("{:016x}", utils::hash_bytes(&bytes)),
+        Err(_) => "missing".to_string(),
+    }
+}
+
+impl BuildManifest {
+    /// Path of the manifest for a main file, in the build directory next to the log file
+    pub fn path(config: &Config, main_file: &Path) -> PathBuf {
+        log::aux_file(config, main_file, "build-manifest")
+    }
+
+    /// Compute the manifest for the current state of the document. Inputs are read from the
+    /// `.fls` recorder file if the compiler created one in the build directory, and from the
+    /// include graph otherwise.
+    pub fn compute(config: &Config, main_file: &Path, command: &str) -> Self {
+        let fls_file = log::aux_file(config, main_file, "fls");
+        let recorder = fls_file.is_file().then_some(fls_file.as_path());
+        let graph = DepGraph::build(&config.root, main_file, recorder);
+        let inputs = graph
+            .files()
+            .into_iter()
+            .map(|d| {
+                let hash = hash_file(&config.root.join(&d.path));
+                (d.path, hash)
+            })
+            .collect();
+
+        Self {
+            command: command.to_string(),
+            inputs,
+        }
+    }
+
+    pub fn read(config: &Config, main_file: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(Self::path(config, main_file)).ok()?;
+        toml::from_str(&contents).ok()
+    }
+
+    pub fn write(&self, config: &Config, main_file: &Path) {
+        let path = Self::path(config, main_file);
+        utils::create_dir_all(utils::parrent(&path));
+        match toml::to_string(self) {
+            Ok(s) => utils::write(&path, s),
+            Err(e) => eprintln!("WARNING: Could not write build manifest: {}", e),
+        }
+    }
+
+    /// Input files which differ between two manifests
+    pub fn changed_inputs(&self, other: &Self) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self
+            .inputs
+            .iter()
+            .filter(|(path, hash)| other.inputs.get(*path) != Some(hash))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(
+            other
+                .inputs
+                .keys()
+                .filter(|path| !self.inputs.contains_key(*path))
+                .cloned(),
+        );
+        changed.sort();
+        changed
+    }
+
+    /// Check whether the document is unchanged since the build which produced this manifest
+    pub fn is_up_to_date(&self, config: &Config, main_file: &Path, command: &str) -> bool {
+        log::find_pdf_file(config, main_file).is_file()
+            && Self::compute(config, main_file, command) == *self
+    }
+}
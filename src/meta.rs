@@ -0,0 +1,228 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{opts::Config, utils};
+
+/// Name of the metadata file in the build directory, used if `meta_file` is not set
+pub const DEFAULT_META_FILE: &str = "blatex-meta.tex";
+
+/// Shown in place of git metadata outside of a git repository
+const UNKNOWN: &str = "unknown";
+
+/// Arguments to git printing the abbreviated commit hash
+const REV_ARGS: &[&str] = &["rev-parse", "--short", "HEAD"];
+
+/// Arguments to git describing the commit relative to the latest tag
+const DESCRIBE_ARGS: &[&str] = &["describe", "--tags", "--always", "--dirty"];
+
+/// The revision of the git repository containing the document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitInfo {
+    pub hash: String,
+
+    /// Abbreviated commit hash
+    pub rev: String,
+
+    /// Current branch, unless the head is detached
+    pub branch: Option<String>,
+
+    /// Latest tag reachable from the commit
+    pub tag: Option<String>,
+
+    /// Output of `git describe --tags --always --dirty`
+    pub describe: String,
+
+    /// Whether tracked files have uncommitted changes
+    pub dirty: bool,
+}
+
+/// Run git in `dir`, returning its trimmed output if it succeeds
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    match output.status.success() {
+        true => Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => None,
+    }
+}
+
+impl GitInfo {
+    /// Read the metadata of the repository containing `dir`. Returns `None` if git is not
+    /// installed, or if `dir` is not in a repository with commits.
+    pub fn read(dir: &Path) -> Option<Self> {
+        Some(Self {
+            hash: git(dir, &["rev-parse", "HEAD"])?,
+            rev: git(dir, REV_ARGS)?,
+            branch: git(dir, &["rev-parse", "--abbrev-ref", "HEAD"]).filter(|b| b != "HEAD"),
+            tag: git(dir, &["describe", "--tags", "--abbrev=0"]),
+            describe: git(dir, DESCRIBE_ARGS)?,
+            dirty: !git(dir, &["status", "--porcelain", "--untracked-files=no"])?.is_empty(),
+        })
+    }
+}
+
+/// Replace the git placeholders `<git-rev>` and `<git-describe>` in `s`. Git is only run for the
+/// placeholders `s` contains.
+pub fn replace_git_placeholders(s: &str, root: &Path) -> String {
+    let mut out = s.to_string();
+    for (placeholder, args) in [("<git-rev>", REV_ARGS), ("<git-describe>", DESCRIBE_ARGS)] {
+        if out.contains(placeholder) {
+            let value = git(root, args).unwrap_or(UNKNOWN.to_string());
+            out = utils::replace_text(&out, placeholder, &value);
+        }
+    }
+    out
+}
+
+/// The date of a number of days since 1970-01-01 as year, month and day
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    // Count from 0000-03-01, such that leap days are at the end of the 400 year eras
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[test]
+fn test_civil_date() {
+    assert_eq!(civil_date(0), (1970, 1, 1));
+    assert_eq!(civil_date(11016), (2000, 2, 29));
+    assert_eq!(civil_date(20745), (2026, 10, 19));
+    assert_eq!(civil_date(-1), (1969, 12, 31));
+}
+
+/// The current date in UTC as `YYYY-MM-DD`. `SOURCE_DATE_EPOCH` is used instead of the current
+/// time if it is set, for reproducible builds.
+fn build_date() -> String {
+    let seconds = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default()
+        });
+    let (year, month, day) = civil_date(seconds.div_euclid(86400));
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Escape text such that TeX typesets it as is
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\\' => "\\textbackslash{}".to_string(),
+            '~' => "\\textasciitilde{}".to_string(),
+            '^' => "\\textasciicircum{}".to_string(),
+            '_' | '#' | '%' | '&' | '$' | '{' | '}' => format!("\\{c}"),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape("feature/new_plot"), "feature/new\\_plot");
+    assert_eq!(
+        escape("v1.0~rc^1"),
+        "v1.0\\textasciitilde{}rc\\textasciicircum{}1"
+    );
+}
+
+/// The contents of the metadata file, defining a macro for each piece of metadata
+fn meta_source(info: Option<&GitInfo>, date: &str, target: &str) -> String {
+    let field = |f: fn(&GitInfo) -> Option<&str>| {
+        escape(info.and_then(f).unwrap_or(match info {
+            Some(_) => "",
+            None => UNKNOWN,
+        }))
+    };
+    let dirty = match info.is_some_and(|i| i.dirty) {
+        true => "true",
+        false => "false",
+    };
+
+    let mut out = "% Generated by blatex before each compilation. Do not edit.\n".to_string();
+    for (name, value) in [
+        ("GitHash", field(|i| Some(&i.hash))),
+        ("GitRev", field(|i| Some(&i.rev))),
+        ("GitBranch", field(|i| i.branch.as_deref())),
+        ("GitTag", field(|i| i.tag.as_deref())),
+        ("GitDescribe", field(|i| Some(&i.describe))),
+        ("BuildDate", date.to_string()),
+        ("Target", escape(target)),
+    ] {
+        out += &format!("\\def\\blatex{name}{{{value}}}\n");
+    }
+    out += &format!("\\newif\\ifblatexGitDirty\n\\blatexGitDirty{dirty}\n");
+    out
+}
+
+#[test]
+fn test_meta_source() {
+    let info = GitInfo {
+        hash: "0123456789abcdef".to_string(),
+        rev: "0123456".to_string(),
+        branch: Some("main".to_string()),
+        tag: None,
+        describe: "0123456-dirty".to_string(),
+        dirty: true,
+    };
+    let source = meta_source(Some(&info), "2026-10-19", "main");
+    assert!(source.contains("\\def\\blatexGitRev{0123456}\n"));
+    assert!(source.contains("\\def\\blatexGitTag{}\n"));
+    assert!(source.contains("\\def\\blatexBuildDate{2026-10-19}\n"));
+    assert!(source.ends_with("\\blatexGitDirtytrue\n"));
+
+    let source = meta_source(None, "2026-10-19", "main");
+    assert!(source.contains("\\def\\blatexGitHash{unknown}\n"));
+    assert!(source.ends_with("\\blatexGitDirtyfalse\n"));
+}
+
+/// Path of the metadata file written when building into `build_dir`, or `None` if it is turned
+/// off
+pub fn meta_file(config: &Config, build_dir: &Path) -> Option<PathBuf> {
+    match &config.meta_file {
+        None => Some(build_dir.join(DEFAULT_META_FILE)),
+        Some(f) if f.as_os_str().is_empty() => None,
+        Some(f) => Some(config.root.join(f)),
+    }
+}
+
+/// Write the metadata file of the document configured by `meta_file`, unless it is empty. The
+/// file is only written when its contents change, such that unchanged documents stay up to date.
+pub fn write_meta_file(config: &Config, main_file: &Path, build_dir: &Path) {
+    let Some(path) = meta_file(config, build_dir) else {
+        return;
+    };
+    let target = main_file.with_extension("");
+    let source = meta_source(
+        GitInfo::read(&config.root).as_ref(),
+        &build_date(),
+        &target.to_string_lossy(),
+    );
+    if fs::read_to_string(&path).ok().as_deref() != Some(source.as_str()) {
+        // The build directory is only created by the compiler
+        if let Some(dir) = path.parent() {
+            utils::create_dir_all(dir);
+        }
+        utils::write(&path, source);
+    }
+}
@@ -1,11 +1,17 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use clap::{Parser, Subcommand};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use toml::map::Map;
 
-use crate::{config::LOCAL_CONFIG_FILE, exit_with_error};
+use crate::{
+    config::{self, LOCAL_CONFIG_FILE},
+    exit_with_error, utils,
+};
 
 pub const REMOTE_TEMPLATES_OPTION: &str = "remote_templates";
 
@@ -21,7 +27,7 @@ pub struct Args {
 
     /// Path to local configuration file
     #[arg(short('C'), long)]
-    config_path: Option<String>,
+    pub config_path: Option<String>,
 }
 
 #[derive(Subcommand, Clone)]
@@ -38,12 +44,46 @@ pub enum Command {
     /// Show errors and warnings from the last compilation
     Log(LogArgs),
 
+    /// Open the compiled document in a PDF viewer
+    View(ViewArgs),
+
+    /// Find positions in the PDF from source lines and back using SyncTeX
+    Synctex(SynctexArgs),
+
+    /// Run a language server over stdin and stdout, showing diagnostics when files are saved
+    Lsp,
+
+    /// Print a shell completion script
+    Completions(CompletionsArgs),
+
+    /// Write the man pages of blatex and its configuration files
+    Man(ManArgs),
+
+    /// Show the files the document is built from
+    Deps(DepsArgs),
+
+    /// Collect the files the document is built from into a zip or tar.gz archive
+    Archive(ArchiveArgs),
+
+    /// Prepare a tarball for submission to arXiv
+    Arxiv(ArxivArgs),
+
+    /// Inline the files included by the document into a single `.tex` file
+    Flatten(FlattenArgs),
+
+    /// Compile a PDF marking the changes between two git revisions of the document
+    Diff(DiffArgs),
+
     /// Commands for managing templates
     Template(TemplateArgs),
 
     /// Alias for `template add`
     Add(TemplateAddArgs),
 
+    /// Create a new file in the document from a file template
+    #[command(visible_alias = "scaffold")]
+    AddFile(AddFileArgs),
+
     /// Manage blatex configuration
     Config(ConfigArgs),
 }
@@ -59,11 +99,56 @@ pub struct InitArgs {
     pub main: Option<String>,
 }
 
-#[derive(Clone, clap::Args)]
+#[derive(Clone, Default, clap::Args)]
 pub struct CompileArgs {
     /// Entry point for the latex compiler
     #[clap(index = 1)]
     pub main_file: Option<String>,
+
+    /// Compile even if no files have changed since the last compilation
+    #[arg(short, long, default_value_t = false)]
+    pub force: bool,
+
+    /// Install missing packages with the package manager of the TeX distribution
+    #[arg(long, default_value_t = false)]
+    pub install: bool,
+
+    /// Hide the output of the compiler and only show errors and warnings from the log
+    #[arg(short, long, default_value_t = false, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Show the output of the compiler and details about the build
+    #[arg(short, long, default_value_t = false)]
+    pub verbose: bool,
+
+    /// Open the document in the PDF viewer after compiling it
+    #[arg(long, default_value_t = false)]
+    pub open: bool,
+
+    /// Show how long each pass of the compilation took and keep a history of build times
+    #[arg(long, default_value_t = false)]
+    pub timings: bool,
+
+    /// Stop the compiler if it runs for longer than this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+
+    /// Fail if the log contains diagnostics of this class
+    #[arg(long, value_enum, value_name = "CLASS")]
+    pub deny: Vec<Deny>,
+
+    #[command(flatten)]
+    pub filter: LogFilterArgs,
+}
+
+/// Classes of diagnostics which can fail a compilation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Deny {
+    /// Any warning
+    Warnings,
+
+    /// Undefined references and citations, and multiply defined labels
+    UndefinedRefs,
 }
 
 #[derive(Clone, clap::Args)]
@@ -73,11 +158,277 @@ pub struct CleanArgs {
     pub main_file: Option<String>,
 }
 
+#[derive(Clone, clap::Args)]
+pub struct ViewArgs {
+    /// Entry point for the latex compiler
+    #[clap(index = 1)]
+    pub main_file: Option<String>,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct ManArgs {
+    /// Directory to write the man pages to
+    #[clap(index = 1)]
+    pub out_dir: String,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct CompletionsArgs {
+    /// Shell to print the completion script for
+    #[clap(index = 1, value_enum)]
+    pub shell: Shell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct SynctexArgs {
+    #[clap(subcommand)]
+    pub synctex_command: SynctexCommand,
+
+    /// Entry point for the latex compiler
+    #[arg(short, long)]
+    pub main_file: Option<String>,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum SynctexCommand {
+    /// Find the page and coordinates typeset from a line of a source file
+    Forward(SynctexForwardArgs),
+
+    /// Find the source file and line typeset at a position in the PDF
+    Inverse(SynctexInverseArgs),
+}
+
+#[derive(Clone, clap::Args)]
+pub struct SynctexForwardArgs {
+    /// Source position like `main.tex:120`
+    #[clap(index = 1)]
+    pub position: String,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct SynctexInverseArgs {
+    /// Page number, starting from 1
+    #[clap(index = 1)]
+    pub page: usize,
+
+    /// Distance from the left edge of the page in big points
+    #[clap(index = 2)]
+    pub x: f64,
+
+    /// Distance from the top edge of the page in big points
+    #[clap(index = 3)]
+    pub y: f64,
+}
+
 #[derive(Clone, clap::Args)]
 pub struct LogArgs {
-    /// Log file to show errors for
+    /// Log file or main file to show errors for. The log file of a main file is found from the
+    /// compilation command.
     #[clap(index = 1)]
     pub log_file: Option<String>,
+
+    /// Show this many lines of source code around each error and warning
+    #[arg(short, long, value_name = "N")]
+    pub context: Option<usize>,
+
+    #[command(flatten)]
+    pub filter: LogFilterArgs,
+}
+
+/// Options for which diagnostics from the log file are shown
+#[derive(Clone, Default, clap::Args)]
+pub struct LogFilterArgs {
+    /// Only show diagnostics of this severity or worse
+    #[arg(long, value_enum, value_name = "LEVEL")]
+    pub only: Option<LogLevel>,
+
+    /// Hide diagnostics containing this text
+    #[arg(long, value_name = "PATTERN")]
+    pub ignore: Vec<String>,
+
+    /// Hide diagnostics from this package
+    #[arg(long, value_name = "PACKAGE")]
+    pub ignore_package: Vec<String>,
+}
+
+/// Least severe kind of diagnostic to show
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    /// Only errors
+    Errors,
+
+    /// Errors and warnings
+    Warnings,
+
+    /// Errors, warnings and bad boxes
+    #[default]
+    All,
+}
+
+impl LogLevel {
+    /// Values accepted in configuration files
+    pub const NAMES: &'static [&'static str] = &["errors", "warnings", "all"];
+}
+
+#[derive(Clone, clap::Args)]
+pub struct AddFileArgs {
+    /// Kind of file to create: chapter, figure, table, tikz, frame or the name of a `.tex` file
+    /// in a template directory
+    #[clap(index = 1)]
+    pub kind: String,
+
+    /// Name of the new file
+    #[clap(index = 2)]
+    pub name: String,
+
+    /// Path of the new file relative to the document root
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Insert an `\input` or `\include` of the new file into the main file
+    #[arg(short, long, default_value_t = false)]
+    pub include: bool,
+
+    /// Override existing file
+    #[arg(short, long, default_value_t = false)]
+    pub force: bool,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct DepsArgs {
+    /// Entry point for the latex compiler
+    #[clap(index = 1)]
+    pub main_file: Option<String>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = DepsFormat::Tree)]
+    pub format: DepsFormat,
+
+    /// Also include files listed in the `.fls` file created by the latex compiler with `-recorder`
+    #[arg(short, long, default_value_t = false)]
+    pub recorder: bool,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct ArchiveArgs {
+    /// Entry point for the latex compiler
+    #[clap(index = 1)]
+    pub main_file: Option<String>,
+
+    /// File to write the archive to. Defaults to the main file with the extension of the format.
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Archive format. Found from the extension of the output file if not given.
+    #[arg(long, value_enum)]
+    pub format: Option<ArchiveFormat>,
+
+    /// Also include files listed in the `.fls` file created by the latex compiler with `-recorder`
+    #[arg(short, long, default_value_t = false)]
+    pub recorder: bool,
+
+    /// Check that the archive compiles on its own by compiling it in a temporary directory
+    #[arg(long, default_value_t = false)]
+    pub verify: bool,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct ArxivArgs {
+    /// Entry point for the latex compiler
+    #[clap(index = 1)]
+    pub main_file: Option<String>,
+
+    /// File to write the tarball to. Defaults to `<main-stem>-arxiv.tar.gz`.
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Move all files to the top directory and update the paths in `\input` and similar commands
+    #[arg(long, default_value_t = false)]
+    pub flatten: bool,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct FlattenArgs {
+    /// Entry point for the latex compiler
+    #[clap(index = 1)]
+    pub main_file: Option<String>,
+
+    /// File to write the flattened document to. Defaults to `<main-stem>-flat.tex`.
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Replace `\bibliography` with the bibliography generated by the last compilation
+    #[arg(long, default_value_t = false)]
+    pub bbl: bool,
+}
+
+#[derive(Clone, clap::Args)]
+pub struct DiffArgs {
+    /// Revision of the old version
+    #[clap(index = 1)]
+    pub old: String,
+
+    /// Revision of the new version. Defaults to the working tree.
+    #[clap(index = 2)]
+    pub new: Option<String>,
+
+    /// Entry point for the latex compiler
+    #[arg(short, long)]
+    pub main_file: Option<String>,
+
+    /// Program marking the changes. Defaults to latexdiff if it is installed.
+    #[arg(long, value_enum)]
+    pub differ: Option<Differ>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Differ {
+    /// Run latexdiff
+    Latexdiff,
+
+    /// Mark changed words with the built-in differ
+    Words,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum DepsFormat {
+    /// Tree of includes
+    Tree,
+
+    /// Flat list of files
+    List,
+
+    /// Makefile rule for the output pdf
+    Make,
+
+    /// Graphviz DOT graph
+    Dot,
 }
 
 #[derive(Clone, clap::Args)]
@@ -105,7 +456,14 @@ pub enum TemplateCommand {
     AddRepo(TemplateAddRepoArgs),
 
     /// List templates
-    List,
+    List(TemplateListArgs),
+}
+
+#[derive(Clone, clap::Args)]
+pub struct TemplateListArgs {
+    /// Only print the names of the templates, one per line
+    #[arg(long, default_value_t = false)]
+    pub names: bool,
 }
 
 #[derive(Clone, clap::Args)]
@@ -157,6 +515,9 @@ pub enum ConfigCommand {
 
     /// Dump the current configuration to stdout
     Show,
+
+    /// Check configuration files for unknown keys and invalid values
+    Check,
 }
 
 #[derive(Clone, clap::Args)]
@@ -176,9 +537,17 @@ pub struct Config {
     pub main_file: PathBuf,
 
     /// Command for compiling document. \<main-file\> will be substituted with the `main_file`
-    /// configuration field.
+    /// configuration field, and \<git-rev\> and \<git-describe\> with the git revision.
     pub compile_cmd: String,
 
+    /// Number of seconds the compilation command may run before it is stopped
+    pub compile_timeout: Option<u64>,
+
+    /// Command for opening the compiled document. \<pdf\> will be substituted with the path to the
+    /// PDF file, which is otherwise added as the last argument. If unset, the first of zathura,
+    /// evince, okular and xdg-open which is installed is used.
+    pub viewer: Option<String>,
+
     /// Command for cleaning temporary document files. \<main-file\> will be substituted with the `main_file`
     /// configuration field.
     pub clean_cmd: String,
@@ -189,6 +558,10 @@ pub struct Config {
     /// Directory for storing templates
     pub templates_dir: PathBuf,
 
+    /// Additional directories containing templates. They are searched in order before
+    /// `templates_dir`.
+    pub template_dirs: Vec<PathBuf>,
+
     /// Directory used for configuration of blatex
     pub config_file: PathBuf,
 
@@ -197,6 +570,19 @@ pub struct Config {
 
     /// Remote templates and their options
     pub remote_templates: HashMap<String, RemoteTemplate>,
+
+    /// Least severe kind of diagnostic shown from the log file: `errors`, `warnings` or `all`
+    pub log_level: LogLevel,
+
+    /// Diagnostics containing any of these texts are hidden
+    pub log_ignore: Vec<String>,
+
+    /// Diagnostics from these packages are hidden
+    pub log_ignore_packages: Vec<String>,
+
+    /// File written with the git revision and build date before each compilation, relative to
+    /// the root. `blatex-meta.tex` in the build directory if not set, and not written if empty.
+    pub meta_file: Option<PathBuf>,
 }
 
 fn get_cwd() -> PathBuf {
@@ -222,111 +608,257 @@ impl Default for Config {
             root,
             data_dir,
             templates_dir,
+            template_dirs: vec![],
             config_file: config_dir,
             temp_dir,
             main_file: PathBuf::from("main.tex"),
             compile_cmd: "pdflatex -shell-escape -interaction=nonstopmode <main-file>".to_string(),
+            compile_timeout: None,
+            viewer: None,
             clean_cmd: "rm <main-stem>.aux <main-stem>.log".to_string(),
             remote_templates: HashMap::new(),
+            log_level: LogLevel::default(),
+            log_ignore: vec![],
+            log_ignore_packages: vec![],
+            meta_file: None,
         }
     }
 }
 
-impl Config {
-    pub fn override_some_fields(config: &mut Config, map: &Map<String, toml::Value>) {
-        Self::override_pathbuf_if_some_string(&mut config.data_dir, map.get("data_dir"));
-        Self::override_pathbuf_if_some_string(&mut config.templates_dir, map.get("templates_dir"));
-        Self::override_pathbuf_if_some_string(&mut config.config_file, map.get("config_file"));
-        Self::override_pathbuf_if_some_string(&mut config.temp_dir, map.get("temp_dir"));
-        Self::override_pathbuf_if_some_string(&mut config.main_file, map.get("main_file"));
-        Self::override_if_some_string(&mut config.compile_cmd, map.get("compile_cmd"));
-        Self::override_if_some_string(&mut config.clean_cmd, map.get("clean_cmd"));
+/// A configuration where every field is optional. Configuration files are read into partial
+/// configurations which are then merged on top of each other.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct PartialConfig {
+    pub root: Option<PathBuf>,
+    pub main_file: Option<PathBuf>,
+    pub compile_cmd: Option<String>,
+    pub compile_timeout: Option<u64>,
+    pub viewer: Option<String>,
+    pub clean_cmd: Option<String>,
+    pub data_dir: Option<PathBuf>,
+    pub templates_dir: Option<PathBuf>,
+    pub template_dirs: Option<Vec<PathBuf>>,
+    pub config_file: Option<PathBuf>,
+    pub temp_dir: Option<PathBuf>,
+    pub remote_templates: Option<HashMap<String, RemoteTemplate>>,
+    pub log_level: Option<LogLevel>,
+    pub log_ignore: Option<Vec<String>>,
+    pub log_ignore_packages: Option<Vec<String>>,
+    pub meta_file: Option<PathBuf>,
+
+    /// Configuration files this configuration is based on. Options in this configuration
+    /// override the ones in the extended files.
+    pub extends: Option<Vec<PathBuf>>,
+}
+
+impl From<&Config> for PartialConfig {
+    fn from(config: &Config) -> Self {
+        Self {
+            root: Some(config.root.clone()),
+            main_file: Some(config.main_file.clone()),
+            compile_cmd: Some(config.compile_cmd.clone()),
+            compile_timeout: config.compile_timeout,
+            viewer: config.viewer.clone(),
+            clean_cmd: Some(config.clean_cmd.clone()),
+            data_dir: Some(config.data_dir.clone()),
+            templates_dir: Some(config.templates_dir.clone()),
+            template_dirs: Some(config.template_dirs.clone()),
+            config_file: Some(config.config_file.clone()),
+            temp_dir: Some(config.temp_dir.clone()),
+            remote_templates: Some(config.remote_templates.clone()),
+            log_level: Some(config.log_level),
+            log_ignore: Some(config.log_ignore.clone()),
+            log_ignore_packages: Some(config.log_ignore_packages.clone()),
+            meta_file: config.meta_file.clone(),
+            extends: None,
+        }
     }
+}
 
-    fn override_pathbuf_if_some_string(var: &mut PathBuf, value: Option<&toml::Value>) {
-        if let Some(toml::Value::String(s)) = value {
-            *var = PathBuf::from(s);
+impl PartialConfig {
+    /// Read and validate a configuration file
+    pub fn try_from_file(file: &Path) -> Result<Self, String> {
+        let s = fs::read_to_string(file).map_err(|e| e.to_string())?;
+        Self::from_source(file, &s)
+    }
+
+    /// Validate and parse the contents `s` of the configuration file `file`
+    fn from_source(file: &Path, s: &str) -> Result<Self, String> {
+        let issues = config::validate(s);
+        if config::print_issues(file, s, &issues) {
+            return Err(format!("Invalid configuration file `{}`.", file.display()));
+        }
+
+        let mut partial: Self = toml::from_str(s).map_err(|e| e.to_string())?;
+
+        // Paths are relative to the configuration file defining them. An empty `meta_file` turns
+        // the file off, so it is left as is.
+        let meta_file = partial
+            .meta_file
+            .as_mut()
+            .filter(|f| !f.as_os_str().is_empty());
+        for path in [
+            partial.root.as_mut(),
+            partial.data_dir.as_mut(),
+            partial.templates_dir.as_mut(),
+            partial.config_file.as_mut(),
+            partial.temp_dir.as_mut(),
+            meta_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = utils::resolve_config_path(file, path);
+        }
+        for paths in [&mut partial.extends, &mut partial.template_dirs]
+            .into_iter()
+            .flatten()
+        {
+            for path in paths.iter_mut() {
+                *path = utils::resolve_config_path(file, path);
+            }
         }
+
+        Ok(partial)
+    }
+
+    /// Read a configuration file and every file it extends. The layers are returned in the order
+    /// they should be merged, such that later layers override earlier ones.
+    pub fn try_layers(file: &Path) -> Result<Vec<Self>, String> {
+        let partial = Self::try_from_file(file)?;
+        let mut layers = vec![];
+        Self::collect_layers(file, partial, &mut vec![], &mut layers)?;
+        Ok(layers)
     }
 
-    fn override_if_some_string(var: &mut String, value: Option<&toml::Value>) {
-        if let Some(toml::Value::String(s)) = value {
-            *var = s.clone();
+    /// Add the layers of the files extended by `partial`, read from `file`, followed by `partial`
+    fn collect_layers(
+        file: &Path,
+        partial: Self,
+        stack: &mut Vec<PathBuf>,
+        layers: &mut Vec<Self>,
+    ) -> Result<(), String> {
+        let canonical = file.canonicalize().unwrap_or(file.to_path_buf());
+        if let Some(i) = stack.iter().position(|f| f == &canonical) {
+            return Err(format!(
+                "Configuration files extend each other in a cycle: {}",
+                utils::format_cycle(&stack[i..], &canonical)
+            ));
         }
+
+        stack.push(canonical);
+        for extended in partial.extends.iter().flatten() {
+            let extended_partial = Self::try_from_file(extended).map_err(|e| {
+                format!(
+                    "Could not read configuration file `{}` extended by `{}`: {}",
+                    extended.display(),
+                    file.display(),
+                    e
+                )
+            })?;
+            Self::collect_layers(extended, extended_partial, stack, layers)?;
+        }
+        stack.pop();
+
+        layers.push(partial);
+        Ok(())
     }
+}
+
+impl Config {
+    /// Override every field that is set in the partial configuration. Remote templates and log
+    /// filters are added to the existing ones, and template directories are searched before the
+    /// existing ones.
+    pub fn merge(&mut self, partial: PartialConfig) {
+        let PartialConfig {
+            root,
+            main_file,
+            compile_cmd,
+            compile_timeout,
+            viewer,
+            clean_cmd,
+            data_dir,
+            templates_dir,
+            template_dirs,
+            config_file,
+            temp_dir,
+            remote_templates,
+            log_level,
+            log_ignore,
+            log_ignore_packages,
+            meta_file,
+            extends: _,
+        } = partial;
+
+        macro_rules! merge_fields {
+            ($($field:ident),+) => {
+                $(if let Some(v) = $field {
+                    self.$field = v;
+                })+
+            };
+        }
+
+        merge_fields!(
+            root,
+            main_file,
+            compile_cmd,
+            clean_cmd,
+            data_dir,
+            templates_dir,
+            config_file,
+            temp_dir,
+            log_level
+        );
+
+        if compile_timeout.is_some() {
+            self.compile_timeout = compile_timeout;
+        }
+
+        if viewer.is_some() {
+            self.viewer = viewer;
+        }
 
-    fn parse_toml(s: &str) -> Map<String, toml::Value> {
-        match toml::from_str(s) {
-            Ok(c) => c,
-            Err(e) => exit_with_error!("{e}"),
+        if meta_file.is_some() {
+            self.meta_file = meta_file;
         }
+
+        if let Some(mut template_dirs) = template_dirs {
+            template_dirs.append(&mut self.template_dirs);
+            self.template_dirs = template_dirs;
+        }
+
+        if let Some(remote_templates) = remote_templates {
+            self.remote_templates.extend(remote_templates);
+        }
+
+        self.log_ignore.extend(log_ignore.into_iter().flatten());
+        self.log_ignore_packages
+            .extend(log_ignore_packages.into_iter().flatten());
     }
 
+    /// The default configuration with the global configuration file applied. Errors are fatal.
     pub fn new_global() -> Self {
-        let mut config = Config::default();
+        Self::try_new_global().unwrap_or_else(|e| exit_with_error!("{}", e))
+    }
 
+    /// Like `new_global`, but returns an error if the configuration file cannot be read
+    pub fn try_new_global() -> Result<Self, String> {
+        let mut config = Config::default();
         if config.config_file.is_file() {
-            let global_toml = match fs::read_to_string(&config.config_file) {
-                Ok(toml) => toml,
-                Err(e) => {
-                    eprintln!(
-                        "Could not read global configuration file at '{}': {}",
-                        config.config_file.display(),
-                        e
-                    );
-                    return config;
-                }
-            };
-            let global_config: Map<String, toml::Value> = Self::parse_toml(global_toml.as_str());
-
-            if let Some(toml::Value::Table(table)) = global_config.get(REMOTE_TEMPLATES_OPTION) {
-                for (name, value) in table.iter() {
-                    let remote_template = match value {
-                        toml::Value::String(url) => RemoteTemplate::from_url(url.clone()),
-                        toml::Value::Table(fields) => {
-                            let url = match fields.get("repo") {
-                                Some(toml::Value::String(r)) => r.clone(),
-                                Some(_) => {
-                                    exit_with_error!("ERROR: Repository url must be string.")
-                                }
-                                None => exit_with_error!(
-                                    "ERROR: Repository for '{}' is not defined.",
-                                    name
-                                ),
-                            };
-                            let path = match fields.get("path") {
-                                Some(toml::Value::String(p)) => Some(PathBuf::from(p)),
-                                _ => None,
-                            };
-                            let branch = match fields.get("branch") {
-                                Some(toml::Value::String(b)) => Some(b.clone()),
-                                _ => None,
-                            };
-
-                            let mut remote_config = Config::default();
-                            Self::override_some_fields(&mut remote_config, fields);
-
-                            RemoteTemplate::new(url, path, branch, remote_config)
-                        }
-                        _ => exit_with_error!(
-                            "Error in remote template '{}'. Must be string or table of options.",
-                            name
-                        ),
-                    };
-                    config
-                        .remote_templates
-                        .insert(name.clone(), remote_template);
-                }
-            }
-
-            Self::override_some_fields(&mut config, &global_config);
+            let layers = PartialConfig::try_layers(&config.config_file).map_err(|e| {
+                format!(
+                    "Could not read global configuration file at '{}': {}",
+                    config.config_file.display(),
+                    e
+                )
+            })?;
+            layers.into_iter().for_each(|l| config.merge(l));
         }
-
-        config
+        Ok(config)
     }
 
     /// Returns (root, local_config_path)
-    fn find_local_config(dir: &PathBuf) -> Option<(PathBuf, PathBuf)> {
+    pub fn find_local_config(dir: &PathBuf) -> Option<(PathBuf, PathBuf)> {
         let config_path = dir.join(LOCAL_CONFIG_FILE);
         if config_path.exists() {
             Some((dir.to_owned(), config_path))
@@ -336,11 +868,19 @@ impl Config {
         }
     }
 
+    /// The global configuration with the local configuration file applied. The local file is
+    /// `provided_config_file`, or else the first one found in `cwd` or its parents. Errors are
+    /// fatal.
     pub fn new_local(cwd: &PathBuf, provided_config_file: Option<PathBuf>) -> Self {
-        let mut config = Config::new_global();
-
-        let default_config = provided_config_file.is_none();
+        Self::try_new_local(cwd, provided_config_file).unwrap_or_else(|e| exit_with_error!("{}", e))
+    }
 
+    /// Like `new_local`, but returns an error if a configuration file cannot be read
+    pub fn try_new_local(
+        cwd: &PathBuf,
+        provided_config_file: Option<PathBuf>,
+    ) -> Result<Self, String> {
+        let mut config = Config::try_new_global()?;
         let local_config_file = match provided_config_file {
             Some(p) => p,
             None => match Self::find_local_config(cwd) {
@@ -348,36 +888,68 @@ impl Config {
                     config.root = root;
                     p
                 }
-                None => {
-                    // We cannot find a local config
-                    return config;
-                }
+                None => return Ok(config),
             },
         };
-
-        if let Ok(toml) = fs::read_to_string(&local_config_file) {
-            let local_config: Map<String, toml::Value> = Self::parse_toml(toml.as_str());
-            Self::override_some_fields(&mut config, &local_config);
-        } else if !default_config {
-            eprintln!(
-                "Could not read local config file `{}`. Skipping.",
-                local_config_file.display()
-            );
-        }
-        config
+        let layers = PartialConfig::try_layers(&local_config_file).map_err(|e| {
+            format!(
+                "Could not read local config file `{}`: {}",
+                local_config_file.display(),
+                e
+            )
+        })?;
+        layers.into_iter().for_each(|l| config.merge(l));
+        Ok(config)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Clone)]
 pub struct RemoteTemplate {
+    #[serde(rename = "repo")]
     pub url: String,
     pub path: Option<PathBuf>,
     pub branch: Option<String>,
-    pub config: Config,
+
+    /// Options which are used when initializing a document from the template
+    #[serde(flatten)]
+    pub config: PartialConfig,
+}
+
+/// Remote templates can be written either as a bare repository url or as a table of options
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RemoteTemplateDef {
+    Url(String),
+    Table {
+        repo: String,
+        path: Option<PathBuf>,
+        branch: Option<String>,
+        #[serde(flatten)]
+        config: Box<PartialConfig>,
+    },
+}
+
+impl<'de> Deserialize<'de> for RemoteTemplate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match RemoteTemplateDef::deserialize(deserializer)? {
+            RemoteTemplateDef::Url(url) => Self::from_url(url),
+            RemoteTemplateDef::Table {
+                repo,
+                path,
+                branch,
+                config,
+            } => Self::new(repo, path, branch, *config),
+        })
+    }
 }
 
 impl RemoteTemplate {
-    pub fn new(url: String, path: Option<PathBuf>, branch: Option<String>, config: Config) -> Self {
+    pub fn new(
+        url: String,
+        path: Option<PathBuf>,
+        branch: Option<String>,
+        config: PartialConfig,
+    ) -> Self {
         Self {
             url,
             path,
@@ -387,7 +959,7 @@ impl RemoteTemplate {
     }
 
     pub fn from_url(url: String) -> Self {
-        Self::new(url, None, None, Config::default())
+        Self::new(url, None, None, PartialConfig::default())
     }
 }
 
@@ -407,7 +979,20 @@ impl Opts {
     pub fn create() -> Self {
         let cwd = get_cwd();
         let args = Args::parse();
-        let config = Config::new_local(&cwd, args.config_path.clone().map(PathBuf::from));
+        let config = match &args.command {
+            // Checking the configuration should report problems instead of failing to load
+            Command::Config(ConfigArgs {
+                config_command: ConfigCommand::Check,
+                ..
+            }) => Config::default(),
+            // The language server reports problems to the client, it must not exit or print to
+            // stdout
+            Command::Lsp => Config::try_new_local(&cwd, None).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                Config::default()
+            }),
+            _ => Config::new_local(&cwd, args.config_path.clone().map(PathBuf::from)),
+        };
         Self { args, config, cwd }
     }
 }
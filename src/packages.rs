@@ -0,0 +1,218 @@
+use std::{path::Path, process::Command};
+
+use termion::color::{self, Fg};
+
+use crate::{exit_with_error, log, opts::Config, utils};
+
+/// Index of which TeX Live packages provide which files
+const PACKAGE_INDEX: &str = include_str!("../data/package-index.txt");
+
+/// Extensions of files that are provided by TeX packages
+const PACKAGE_FILE_EXTENSIONS: &[&str] = &[
+    "sty", "cls", "clo", "cfg", "def", "fd", "ldf", "bst", "bbx", "cbx", "lbx",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MissingFile {
+    /// A file like a package or class
+    File(String),
+
+    /// A font metric file
+    Font(String),
+}
+
+impl MissingFile {
+    /// The TeX Live package providing the file
+    pub fn package(&self) -> String {
+        let entries = PACKAGE_INDEX
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| l.split_once(' '));
+
+        match self {
+            MissingFile::File(name) => entries
+                .filter(|(file, _)| file == name)
+                .map(|(_, package)| package.trim().to_string())
+                .next()
+                .unwrap_or_else(|| {
+                    Path::new(name)
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or(name.clone())
+                }),
+            MissingFile::Font(name) => entries
+                .filter_map(|(prefix, package)| Some((prefix.strip_prefix("font:")?, package)))
+                .filter(|(prefix, _)| name.starts_with(prefix))
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map(|(_, package)| package.trim().to_string())
+                .unwrap_or(name.clone()),
+        }
+    }
+}
+
+impl std::fmt::Display for MissingFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MissingFile::File(name) => write!(f, "file `{}`", name),
+            MissingFile::Font(name) => write!(f, "font `{}`", name),
+        }
+    }
+}
+
+/// Get the text between two delimiters
+fn between<'a>(s: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let (_, rest) = s.split_once(start)?;
+    let (middle, _) = rest.split_once(end)?;
+    Some(middle)
+}
+
+fn is_package_file(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| PACKAGE_FILE_EXTENSIONS.contains(&e))
+        .unwrap_or(false)
+}
+
+/// Find files which the latex compiler could not find in the lines of a log file
+pub fn find_missing(log_lines: &[String]) -> Vec<MissingFile> {
+    let mut missing = vec![];
+
+    for line in log_lines {
+        // Errors start with `!`, or with the file and line with `-file-line-error`
+        let error = match line.strip_prefix("! ") {
+            Some(message) => message.to_string(),
+            None => log::parse_file_line_error(line)
+                .map(|(_, _, message)| message)
+                .unwrap_or_default(),
+        };
+
+        let found = if error.starts_with("LaTeX Error: File `") {
+            between(&error, "`", "' not found")
+                .filter(|name| is_package_file(name))
+                .map(|name| MissingFile::File(name.to_string()))
+        } else if error.starts_with("I can't find file `") {
+            between(&error, "`", "'")
+                .filter(|name| is_package_file(name))
+                .map(|name| MissingFile::File(name.to_string()))
+        } else if error.starts_with("Font ") && error.contains("Metric (TFM) file not found") {
+            between(&error, "=", " ").map(|name| MissingFile::Font(name.to_string()))
+        } else if let Some(rest) = line.strip_prefix("kpathsea: Running mktextfm ") {
+            rest.split_whitespace()
+                .next()
+                .map(|name| MissingFile::Font(name.to_string()))
+        } else {
+            None
+        };
+
+        if let Some(f) = found {
+            if !missing.contains(&f) {
+                missing.push(f);
+            }
+        }
+    }
+
+    missing
+}
+
+#[test]
+fn test_find_missing() {
+    let log = [
+        "! LaTeX Error: File `tikz-cd.sty' not found.",
+        "! LaTeX Error: File `chapters/intro.tex' not found.",
+        "! LaTeX Error: File `scrartcl.cls' not found.",
+        "! Font T1/cmr/m/n/10=ecrm1000 at 10.0pt not loadable: Metric (TFM) file not found.",
+        "! LaTeX Error: File `tikz-cd.sty' not found.",
+        "./main.tex:3: LaTeX Error: File `siunitx.sty' not found.",
+        "./main.tex:4: I can't find file `chapters/missing.tex'.",
+    ]
+    .map(String::from);
+
+    let missing = find_missing(&log);
+    assert_eq!(
+        missing,
+        vec![
+            MissingFile::File("tikz-cd.sty".to_string()),
+            MissingFile::File("scrartcl.cls".to_string()),
+            MissingFile::Font("ecrm1000".to_string()),
+            MissingFile::File("siunitx.sty".to_string()),
+        ]
+    );
+    let packages: Vec<String> = missing.iter().map(|m| m.package()).collect();
+    assert_eq!(packages, vec!["tikz-cd", "koma-script", "ec", "siunitx"]);
+}
+
+/// Whether the TeX distribution on this system is MiKTeX. Its package names differ from the
+/// TeX Live names in the package index.
+fn is_miktex() -> bool {
+    utils::find_executable("tlmgr").is_none() && utils::find_executable("miktex").is_some()
+}
+
+/// Look for missing packages in the log of the last compilation and suggest how to install them.
+/// If `install` is set, the packages are installed. Returns true if packages were installed.
+pub fn check_missing_packages(config: &Config, main_file: &Path, install: bool) -> bool {
+    let log_file = log::find_log_file(config, main_file);
+    if !log_file.is_file() {
+        return false;
+    }
+
+    let missing = find_missing(&log::read_log_lines(&log_file));
+    if missing.is_empty() {
+        return false;
+    }
+
+    if is_miktex() {
+        println!("\n{}Missing TeX files:{}", Fg(color::Red), Fg(color::Reset));
+        for m in &missing {
+            println!("  {}", m);
+        }
+        println!(
+            "\nInstall the MiKTeX packages providing them with the MiKTeX Console, or enable \
+             automatic package installation."
+        );
+        if install {
+            eprintln!("WARNING: Cannot look up MiKTeX package names. Install them yourself.");
+        }
+        return false;
+    }
+
+    let mut packages: Vec<String> = vec![];
+    println!(
+        "\n{}Missing TeX packages:{}",
+        Fg(color::Red),
+        Fg(color::Reset)
+    );
+    for m in &missing {
+        let package = m.package();
+        println!("  {} (package `{}`)", m, package);
+        if !packages.contains(&package) {
+            packages.push(package);
+        }
+    }
+
+    let mut cmd = vec!["tlmgr".to_string(), "install".to_string()];
+    cmd.extend(packages);
+    if !install {
+        println!(
+            "\nInstall them with `{}` or run with `--install`.",
+            cmd.join(" ")
+        );
+        return false;
+    }
+
+    println!(
+        "\n{}Running command: `{}`{}\n",
+        Fg(color::Blue),
+        cmd.join(" "),
+        Fg(color::Reset)
+    );
+    let status = match Command::new(&cmd[0]).args(&cmd[1..]).status() {
+        Ok(s) => s,
+        Err(e) => exit_with_error!("Could not run `{}`: {}", cmd[0], e),
+    };
+    if !status.success() {
+        exit_with_error!("Could not install packages.");
+    }
+    true
+}
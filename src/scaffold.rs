@@ -0,0 +1,228 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use termion::color::{self, Fg};
+
+use crate::{
+    exit_with_error,
+    opts::{AddFileArgs, Config},
+    templates, utils,
+};
+
+/// A small template for a single file within a document
+struct FileTemplate {
+    /// Name used to select the template on the command line
+    kind: &'static str,
+
+    /// Directory the file is created in, relative to the document root
+    dir: &'static str,
+
+    /// Command used to include the file in the main file
+    include_cmd: &'static str,
+
+    /// Contents of the file. `<name>` and `<title>` are substituted.
+    contents: &'static str,
+}
+
+const BUILTIN_FILE_TEMPLATES: &[FileTemplate] = &[
+    FileTemplate {
+        kind: "chapter",
+        dir: "chapters",
+        include_cmd: "include",
+        contents: r"\chapter{<title>}
+\label{chap:<name>}
+
+",
+    },
+    FileTemplate {
+        kind: "figure",
+        dir: "figures",
+        include_cmd: "input",
+        contents: r"\begin{figure}[htbp]
+    \centering
+    \includegraphics[width=0.8\textwidth]{<name>}
+    \caption{<title>}
+    \label{fig:<name>}
+\end{figure}
+",
+    },
+    FileTemplate {
+        kind: "table",
+        dir: "tables",
+        include_cmd: "input",
+        contents: r"\begin{table}[htbp]
+    \centering
+    \caption{<title>}
+    \label{tab:<name>}
+    \begin{tabular}{ll}
+        \hline
+        A & B \\
+        \hline
+    \end{tabular}
+\end{table}
+",
+    },
+    FileTemplate {
+        kind: "tikz",
+        dir: "figures",
+        include_cmd: "input",
+        contents: r"\begin{figure}[htbp]
+    \centering
+    \begin{tikzpicture}
+        \draw (0, 0) -- (1, 1);
+    \end{tikzpicture}
+    \caption{<title>}
+    \label{fig:<name>}
+\end{figure}
+",
+    },
+    FileTemplate {
+        kind: "frame",
+        dir: "frames",
+        include_cmd: "input",
+        contents: r"\begin{frame}{<title>}
+    \label{frame:<name>}
+
+\end{frame}
+",
+    },
+];
+
+/// Find a file template in the template directories. File templates are `.tex` files named
+/// after their kind.
+fn find_file_template(config: &Config, kind: &str) -> Option<PathBuf> {
+    templates::template_dirs(config)
+        .into_iter()
+        .map(|dir| dir.join(kind).with_extension("tex"))
+        .find(|p| p.is_file())
+}
+
+/// Names of all available file templates
+fn file_template_kinds(config: &Config) -> Vec<String> {
+    let mut kinds: Vec<String> = BUILTIN_FILE_TEMPLATES
+        .iter()
+        .map(|t| t.kind.to_string())
+        .collect();
+
+    for dir in templates::template_dirs(config) {
+        let entries = match fs::read_dir(dir) {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let path = utils::handle_file_iter(entry).path();
+            if path.extension() != Some(OsStr::new("tex")) {
+                continue;
+            }
+            if let Some(kind) = path.file_stem().and_then(|s| s.to_str()) {
+                if !kinds.iter().any(|k| k == kind) {
+                    kinds.push(kind.to_string());
+                }
+            }
+        }
+    }
+
+    kinds
+}
+
+/// Turn a file name like `related_work` into a title like `Related work`
+fn title_from_name(name: &str) -> String {
+    let words = name.replace(['_', '-'], " ");
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[test]
+fn test_title_from_name() {
+    assert_eq!(title_from_name("related_work"), "Related work");
+    assert_eq!(title_from_name("intro"), "Intro");
+    assert_eq!(title_from_name("data-flow"), "Data flow");
+}
+
+/// Insert `line` into the main file right before `\end{document}`
+fn insert_before_end_document(main_file: &Path, line: &str) {
+    let mut contents = match fs::read_to_string(main_file) {
+        Ok(c) => c,
+        Err(e) => exit_with_error!("Could not read main file '{}': {}", main_file.display(), e),
+    };
+
+    if contents.lines().any(|l| l.trim() == line) {
+        println!("`{}` is already in '{}'.", line, main_file.display());
+        return;
+    }
+
+    let end = match contents.rfind(r"\end{document}") {
+        Some(i) => i,
+        None => exit_with_error!(
+            "Could not find `\\end{{document}}` in main file '{}'.",
+            main_file.display()
+        ),
+    };
+    let line_start = contents[..end].rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+    contents.insert_str(line_start, &format!("{line}\n"));
+    utils::write(main_file, contents);
+
+    println!("Added `{}` to '{}'.", line, main_file.display());
+}
+
+pub fn add_file(config: Config, args: AddFileArgs) {
+    let builtin = BUILTIN_FILE_TEMPLATES.iter().find(|t| t.kind == args.kind);
+
+    // Templates in the template directories take precedence over the builtin ones
+    let contents = match find_file_template(&config, &args.kind) {
+        Some(p) => match fs::read_to_string(&p) {
+            Ok(c) => c,
+            Err(e) => exit_with_error!("Could not read file template '{}': {}", p.display(), e),
+        },
+        None => match builtin {
+            Some(t) => t.contents.to_string(),
+            None => exit_with_error!(
+                "Unknown file template '{}'. Available file templates are: {}.",
+                args.kind,
+                file_template_kinds(&config).join(", ")
+            ),
+        },
+    };
+
+    let contents = utils::replace_text(&contents, "<name>", &args.name);
+    let contents = utils::replace_text(&contents, "<title>", &title_from_name(&args.name));
+
+    // Path of the new file relative to the document root
+    let rel_path = match args.output {
+        Some(o) => PathBuf::from(o),
+        None => PathBuf::from(builtin.map(|t| t.dir).unwrap_or(""))
+            .join(&args.name)
+            .with_extension("tex"),
+    };
+    let dest = config.root.join(&rel_path);
+
+    if !args.force && dest.exists() {
+        exit_with_error!(
+            "File `{}` already exists. Run with --force to override.",
+            dest.display()
+        );
+    }
+
+    utils::create_dir_all(utils::parrent(&dest));
+    utils::write(&dest, contents);
+    println!(
+        "{}Created '{}'.{}",
+        Fg(color::Blue),
+        rel_path.display(),
+        Fg(color::Reset)
+    );
+
+    if args.include {
+        let include_cmd = builtin.map(|t| t.include_cmd).unwrap_or("input");
+        let include_path = rel_path.with_extension("");
+        let line = format!("\\{}{{{}}}", include_cmd, include_path.display());
+        insert_before_end_document(&config.root.join(&config.main_file), &line);
+    }
+}
@@ -0,0 +1,267 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use termion::color::{self, Fg};
+
+use crate::{log, opts::Config, utils};
+
+/// Number of previous builds the duration of a build is compared to
+const HISTORY_LENGTH: usize = 10;
+
+/// A build taking this many times longer than the previous ones is reported
+const SLOWDOWN_FACTOR: f64 = 2.0;
+
+/// Time spent running one tool once
+#[derive(Debug, PartialEq, Eq)]
+struct Segment {
+    tool: String,
+    pass: usize,
+    duration: Duration,
+}
+
+/// Durations of the passes of the tools run by the compilation command. Passes are found from
+/// the `Run number <n> of rule '<tool>'` lines written by latexmk. Everything else is attributed
+/// to the program the command runs.
+pub struct Timings {
+    start: Instant,
+    segments: Vec<Segment>,
+
+    /// Tool, pass and start of the running segment
+    current: (String, usize, Duration),
+
+    /// Output after the last newline
+    partial_line: String,
+}
+
+impl Timings {
+    pub fn start(compile_cmd: &str) -> Self {
+        let program = utils::split_command(compile_cmd)
+            .into_iter()
+            .next()
+            .map(|p| {
+                Path::new(&p)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or(p)
+            })
+            .unwrap_or_default();
+
+        Self {
+            start: Instant::now(),
+            segments: vec![],
+            current: (program, 1, Duration::ZERO),
+            partial_line: String::new(),
+        }
+    }
+
+    /// Update the timings with new output from the compiler
+    pub fn update(&mut self, output: &str) {
+        self.update_at(output, self.start.elapsed());
+    }
+
+    fn update_at(&mut self, output: &str, elapsed: Duration) {
+        self.partial_line.push_str(output);
+        let (lines, rest) = match self.partial_line.rsplit_once('\n') {
+            Some((lines, rest)) => (lines.to_string(), rest.to_string()),
+            None => return,
+        };
+        self.partial_line = rest;
+
+        for line in lines.lines() {
+            let run = line
+                .split_once("Run number ")
+                .and_then(|(_, rest)| rest.split_once(" of rule '"))
+                .and_then(|(n, rest)| Some((n.parse().ok()?, rest.split_once('\'')?.0)));
+            let (pass, rule) = match run {
+                Some(r) => r,
+                None => continue,
+            };
+
+            // Rules are named like `bibtex main`
+            let tool = rule.split_whitespace().next().unwrap_or(rule).to_string();
+            self.end_segment(elapsed);
+            self.current = (tool, pass, elapsed);
+        }
+    }
+
+    fn end_segment(&mut self, elapsed: Duration) {
+        let (tool, pass, start) = &self.current;
+        self.segments.push(Segment {
+            tool: tool.clone(),
+            pass: *pass,
+            duration: elapsed.saturating_sub(*start),
+        });
+    }
+
+    /// End the timing when the compilation command exits
+    pub fn finish(&mut self) {
+        let elapsed = self.start.elapsed();
+        self.end_segment(elapsed);
+    }
+
+    fn total(&self) -> Duration {
+        self.segments.iter().map(|s| s.duration).sum()
+    }
+
+    /// Total time spent in each tool, in the order the tools were first run
+    fn per_tool(&self) -> Vec<(&str, Duration)> {
+        let mut tools: Vec<(&str, Duration)> = vec![];
+        for s in &self.segments {
+            match tools.iter_mut().find(|(t, _)| *t == s.tool) {
+                Some((_, d)) => *d += s.duration,
+                None => tools.push((&s.tool, s.duration)),
+            }
+        }
+        tools
+    }
+}
+
+#[test]
+fn test_timings() {
+    let mut timings = Timings::start("latexmk -pdf <main-file>");
+    let at = Duration::from_millis;
+    timings.update_at(
+        "Latexmk: applying rule 'pdflatex'...\nRun number 1 of",
+        at(100),
+    );
+    timings.update_at(" rule 'pdflatex'\n", at(200));
+    timings.update_at("Run number 1 of rule 'bibtex main'\n", at(1200));
+    timings.update_at("Run number 2 of rule 'pdflatex'\n", at(1500));
+    timings.end_segment(at(2500));
+
+    let segment = |tool: &str, pass, ms| Segment {
+        tool: tool.to_string(),
+        pass,
+        duration: at(ms),
+    };
+    assert_eq!(
+        timings.segments,
+        vec![
+            segment("latexmk", 1, 200),
+            segment("pdflatex", 1, 1000),
+            segment("bibtex", 1, 300),
+            segment("pdflatex", 2, 1000),
+        ]
+    );
+    assert_eq!(
+        timings.per_tool(),
+        vec![
+            ("latexmk", at(200)),
+            ("pdflatex", at(2000)),
+            ("bibtex", at(300))
+        ]
+    );
+}
+
+/// Total durations in seconds of the previous builds
+fn read_history(history: &Path) -> Vec<f64> {
+    fs::read_to_string(history)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| l.split('\t').nth(1)?.parse().ok())
+        .collect()
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 20 => format!("{:.1} MiB", b as f64 / (1 << 20) as f64),
+        b if b >= 1 << 10 => format!("{:.1} KiB", b as f64 / (1 << 10) as f64),
+        b => format!("{b} B"),
+    }
+}
+
+/// Print the timings, page count and size of the output, and add them to the build history
+pub fn report(config: &Config, main_file: &Path, timings: &Timings) {
+    let log_file = log::find_log_file(config, main_file);
+    let pages = match log_file.is_file() {
+        true => log::find_output(&log::read_log_lines(&log_file)).map(|(_, pages)| pages),
+        false => None,
+    };
+    let pdf_file = log::find_pdf_file(config, main_file);
+    let pdf_size = fs::metadata(&pdf_file).ok().map(|m| m.len());
+
+    let name_width = timings
+        .segments
+        .iter()
+        .map(|s| s.tool.len() + format!(" (pass {})", s.pass).len())
+        .max()
+        .unwrap_or(0)
+        .max("Total".len());
+
+    println!("\n{}Timings:{}", Fg(color::Blue), Fg(color::Reset));
+    for s in &timings.segments {
+        let name = format!("{} (pass {})", s.tool, s.pass);
+        println!(
+            "  {:<name_width$}  {:>7.2}s",
+            name,
+            s.duration.as_secs_f64()
+        );
+    }
+    println!();
+    for (tool, duration) in timings.per_tool() {
+        println!("  {:<name_width$}  {:>7.2}s", tool, duration.as_secs_f64());
+    }
+    let total = timings.total().as_secs_f64();
+    println!("  {:<name_width$}  {:>7.2}s", "Total", total);
+
+    if let Some(pages) = pages {
+        println!("\nPages: {}", pages);
+    }
+    if let Some(size) = pdf_size {
+        println!("PDF size: {}", format_size(size));
+    }
+
+    let history = log::aux_file(config, main_file, "build-stats");
+    let previous = read_history(&history);
+    let recent = &previous[previous.len().saturating_sub(HISTORY_LENGTH)..];
+    if !recent.is_empty() {
+        let average = recent.iter().sum::<f64>() / recent.len() as f64;
+        if average > 0.0 && total > average * SLOWDOWN_FACTOR {
+            println!(
+                "\n{}This build took {:.1}x longer than the average of the last {} builds ({:.2}s).{}",
+                Fg(color::Yellow),
+                total / average,
+                recent.len(),
+                average,
+                Fg(color::Reset)
+            );
+        }
+    }
+
+    // timestamp, total seconds, pages, pdf bytes, seconds per tool
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let tools = timings
+        .per_tool()
+        .iter()
+        .map(|(tool, d)| format!("{}={:.3}", tool, d.as_secs_f64()))
+        .collect::<Vec<String>>()
+        .join(",");
+    let line = format!(
+        "{}\t{:.3}\t{}\t{}\t{}\n",
+        timestamp,
+        total,
+        pages.map(|p| p.to_string()).unwrap_or_default(),
+        pdf_size.map(|s| s.to_string()).unwrap_or_default(),
+        tools
+    );
+
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history)
+        .and_then(|mut f| f.write_all(line.as_bytes()));
+    if let Err(e) = written {
+        eprintln!(
+            "WARNING: Could not write build statistics to `{}`: {}",
+            history.display(),
+            e
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+
+use crate::{
+    deps, exit_with_error, log,
+    opts::{Config, SynctexArgs, SynctexCommand},
+};
+
+/// Scaled points per big point, the unit of PDF coordinates
+const SP_PER_BP: f64 = 65781.76;
+
+/// A box or position in the typeset document, in scaled points
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Record {
+    page: usize,
+
+    /// Type of the record: `[` and `(` for vertical and horizontal boxes, `v` and `h` for void
+    /// boxes, and `x`, `k`, `g` and `$` for positions of characters, kerns, glue and math.
+    kind: char,
+
+    /// Input file the record was typeset from
+    tag: usize,
+    line: usize,
+    x: i64,
+    y: i64,
+
+    /// Width, height and depth of boxes
+    size: Option<(i64, i64, i64)>,
+}
+
+impl Record {
+    fn is_box(&self) -> bool {
+        matches!(self.kind, '[' | '(' | 'v' | 'h')
+    }
+
+    /// Check whether a point is inside the box
+    fn contains(&self, x: i64, y: i64) -> bool {
+        match self.size {
+            Some((w, h, d)) => x >= self.x && x <= self.x + w && y >= self.y - h && y <= self.y + d,
+            None => false,
+        }
+    }
+}
+
+/// A position in the PDF, in big points from the top left corner of the page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PdfPosition {
+    pub page: usize,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Contents of a `.synctex` file
+#[derive(Debug, Default)]
+pub struct SyncTex {
+    /// Input files by tag
+    inputs: BTreeMap<usize, PathBuf>,
+    unit: f64,
+    magnification: f64,
+    x_offset: f64,
+    y_offset: f64,
+    records: Vec<Record>,
+}
+
+/// Parse a record like `(1,12:4736286,45847552:30785863,655360,0`
+fn parse_record(page: usize, kind: char, s: &str) -> Option<Record> {
+    let mut parts = s.split(':');
+    let mut link = parts.next()?.split(',');
+    let tag = link.next()?.parse().ok()?;
+    let line = link.next()?.parse().ok()?;
+
+    let mut position = parts.next()?.split(',');
+    let x = position.next()?.parse().ok()?;
+    let y = position.next()?.parse().ok()?;
+
+    let size = parts.next().and_then(|s| {
+        let mut size = s.split(',').map(|n| n.parse().ok());
+        Some((size.next()??, size.next()??, size.next()??))
+    });
+
+    Some(Record {
+        page,
+        kind,
+        tag,
+        line,
+        x,
+        y,
+        size,
+    })
+}
+
+impl SyncTex {
+    pub fn parse(source: &str) -> Self {
+        let mut synctex = SyncTex {
+            unit: 1.0,
+            magnification: 1000.0,
+            ..Default::default()
+        };
+        let mut page = 0;
+
+        for line in source.lines() {
+            if let Some((name, value)) = line.split_once(':') {
+                let number = || value.trim().parse::<f64>().ok();
+                match name {
+                    "Input" => {
+                        if let Some((tag, file)) = value.split_once(':') {
+                            if let Ok(tag) = tag.parse() {
+                                synctex.inputs.insert(tag, deps::normalize(Path::new(file)));
+                            }
+                        }
+                        continue;
+                    }
+                    "Unit" => synctex.unit = number().unwrap_or(1.0),
+                    "Magnification" => synctex.magnification = number().unwrap_or(1000.0),
+                    "X Offset" => synctex.x_offset = number().unwrap_or(0.0),
+                    "Y Offset" => synctex.y_offset = number().unwrap_or(0.0),
+                    _ => {}
+                }
+            }
+
+            let mut chars = line.chars();
+            let kind = match chars.next() {
+                Some(c) => c,
+                None => continue,
+            };
+            let rest = chars.as_str();
+            match kind {
+                '{' => page = rest.parse().unwrap_or(page),
+                '[' | '(' | 'v' | 'h' | 'x' | 'k' | 'g' | '$' => {
+                    if let Some(record) = parse_record(page, kind, rest) {
+                        synctex.records.push(record);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        synctex
+    }
+
+    /// Read a `.synctex` or `.synctex.gz` file
+    pub fn read(path: &Path) -> Self {
+        match Self::try_read(path) {
+            Ok(synctex) => synctex,
+            Err(e) => exit_with_error!("{}", e),
+        }
+    }
+
+    /// Like `read`, but returns an error if the file cannot be read
+    pub fn try_read(path: &Path) -> Result<Self, String> {
+        let mut file =
+            File::open(path).map_err(|e| format!("Could not open `{}`: {}", path.display(), e))?;
+
+        let mut bytes = vec![];
+        let read = match path.extension().is_some_and(|e| e == "gz") {
+            true => GzDecoder::new(file).read_to_end(&mut bytes),
+            false => file.read_to_end(&mut bytes),
+        };
+        read.map_err(|e| format!("Could not read `{}`: {}", path.display(), e))?;
+
+        Ok(Self::parse(&String::from_utf8_lossy(&bytes)))
+    }
+
+    fn to_bp(&self, sp: i64, offset: f64) -> f64 {
+        (sp as f64 * self.unit * self.magnification / 1000.0 + offset) / SP_PER_BP
+    }
+
+    fn to_sp(&self, bp: f64, offset: f64) -> i64 {
+        ((bp * SP_PER_BP - offset) * 1000.0 / (self.magnification * self.unit)) as i64
+    }
+
+    /// Find the position in the PDF typeset from a line of an input file. Relative paths, both of
+    /// `file` and of the inputs in the SyncTeX file, are relative to `root`, where the compiler is
+    /// run. If nothing was typeset from the line itself, the closest line after it is used.
+    pub fn forward(&self, root: &Path, file: &Path, line: usize) -> Option<PdfPosition> {
+        let file = deps::normalize(&root.join(file));
+        let tags: Vec<usize> = self
+            .inputs
+            .iter()
+            .filter(|(_, input)| deps::normalize(&root.join(input)) == file)
+            .map(|(tag, _)| *tag)
+            .collect();
+
+        let records = || self.records.iter().filter(|r| tags.contains(&r.tag));
+        let best_line = records()
+            .map(|r| r.line)
+            .filter(|l| *l >= line)
+            .min()
+            .or_else(|| records().map(|r| r.line).max())?;
+
+        // Prefer the horizontal box of the line over the positions inside it
+        let record = records()
+            .filter(|r| r.line == best_line)
+            .min_by_key(|r| (r.page, r.kind != '('))?;
+
+        Some(PdfPosition {
+            page: record.page,
+            x: self.to_bp(record.x, self.x_offset),
+            y: self.to_bp(record.y, self.y_offset),
+        })
+    }
+
+    /// Find the input file and line typeset at a position in the PDF. The smallest box containing
+    /// the position is used, or the closest position if there is none.
+    pub fn inverse(&self, position: PdfPosition) -> Option<(&Path, usize)> {
+        let x = self.to_sp(position.x, self.x_offset);
+        let y = self.to_sp(position.y, self.y_offset);
+        let on_page = || self.records.iter().filter(|r| r.page == position.page);
+
+        let area = |r: &Record| r.size.map(|(w, h, d)| w * (h + d)).unwrap_or(0);
+        let containing = on_page()
+            .filter(|r| r.contains(x, y) && r.line > 0)
+            .min_by_key(|r| area(r));
+
+        let closest = || {
+            on_page()
+                .filter(|r| !r.is_box() && r.line > 0)
+                .min_by_key(|r| (r.x - x).pow(2) + (r.y - y).pow(2))
+        };
+
+        let record = containing.or_else(closest)?;
+        let file = self.inputs.get(&record.tag)?;
+        Some((file, record.line))
+    }
+}
+
+#[test]
+fn test_synctex() {
+    let source = "SyncTeX Version:1
+Input:1:/doc/./main.tex
+Input:2:/doc/./chapters/intro.tex
+Input:3:./appendix/intro.tex
+Output:pdf
+Magnification:1000
+Unit:1
+X Offset:0
+Y Offset:0
+Content:
+!102
+{1
+[1,5:4736286,3947020:30785863,3947020,0
+(2,3:4736286,3947020:30785863,655360,0
+x2,3:4736286,3947020
+g2,3:8000000,3947020
+)
+(1,7:4736286,6578176:30785863,655360,0
+)
+]
+}1
+{2
+(2,12:4736286,3947020:30785863,655360,0
+)
+(3,2:4736286,6578176:30785863,655360,0
+)
+}2
+Postamble:
+";
+    let synctex = SyncTex::parse(source);
+
+    let root = Path::new("/doc");
+    let position = synctex
+        .forward(root, Path::new("/doc/chapters/intro.tex"), 3)
+        .unwrap();
+    assert_eq!(position.page, 1);
+    assert!((position.x - 72.0).abs() < 0.01);
+    assert!((position.y - 60.0).abs() < 0.01);
+
+    // Lines without output use the next line with output
+    let position = synctex
+        .forward(root, Path::new("chapters/intro.tex"), 10)
+        .unwrap();
+    assert_eq!(position.page, 2);
+
+    // Files with the same name in different directories are told apart
+    let position = synctex
+        .forward(root, Path::new("/doc/appendix/intro.tex"), 1)
+        .unwrap();
+    assert!((position.y - 100.0).abs() < 0.01);
+    assert!(synctex.forward(root, Path::new("intro.tex"), 1).is_none());
+
+    let (file, line) = synctex
+        .inverse(PdfPosition {
+            page: 1,
+            x: 100.0,
+            y: 98.0,
+        })
+        .unwrap();
+    assert_eq!((file, line), (Path::new("/doc/main.tex"), 7));
+}
+
+/// Path of the SyncTeX file produced together with the PDF of `main_file`
+pub fn find_synctex_file(config: &Config, main_file: &Path) -> PathBuf {
+    let pdf = log::find_pdf_file(config, main_file);
+    let gz = pdf.with_extension("synctex.gz");
+    match gz.is_file() {
+        true => gz,
+        false => pdf.with_extension("synctex"),
+    }
+}
+
+pub fn synctex(config: Config, args: SynctexArgs) {
+    let main_file = match &args.main_file {
+        Some(f) => PathBuf::from(f),
+        None => config.main_file.clone(),
+    };
+
+    let synctex_file = find_synctex_file(&config, &main_file);
+    if !synctex_file.is_file() {
+        exit_with_error!(
+            "Cannot find `{}`. Compile the document with `-synctex=1` first.",
+            synctex_file.display()
+        );
+    }
+    let synctex = SyncTex::read(&synctex_file);
+
+    match args.synctex_command {
+        SynctexCommand::Forward(args) => {
+            let (file, line) = match args.position.rsplit_once(':') {
+                Some((file, line)) => match line.parse() {
+                    Ok(l) => (file.to_string(), l),
+                    Err(_) => exit_with_error!("Invalid line number `{}`.", line),
+                },
+                None => exit_with_error!("Expected a position like `main.tex:120`."),
+            };
+            match synctex.forward(&config.root, Path::new(&file), line) {
+                Some(p) => {
+                    println!("Page:{}", p.page);
+                    println!("x:{:.2}", p.x);
+                    println!("y:{:.2}", p.y);
+                }
+                None => {
+                    exit_with_error!("Nothing in the PDF was typeset from `{}`.", args.position)
+                }
+            }
+        }
+        SynctexCommand::Inverse(args) => {
+            let position = PdfPosition {
+                page: args.page,
+                x: args.x,
+                y: args.y,
+            };
+            match synctex.inverse(position) {
+                Some((file, line)) => {
+                    let root = deps::normalize(&config.root);
+                    println!(
+                        "Input:{}",
+                        file.strip_prefix(&root).unwrap_or(file).display()
+                    );
+                    println!("Line:{}", line);
+                }
+                None => exit_with_error!("Nothing was typeset at that position."),
+            }
+        }
+    }
+}
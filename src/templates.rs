@@ -12,13 +12,19 @@ use termion::{
 
 use crate::{
     exit_with_error,
-    opts::{Config, RemoteTemplate, TemplateAddArgs, TemplateAddRepoArgs},
+    opts::{Config, RemoteTemplate, TemplateAddArgs, TemplateAddRepoArgs, TemplateListArgs},
     utils,
 };
 
 #[derive(Debug)]
 pub enum Template {
-    Local(PathBuf),
+    Local {
+        /// The template directory containing the template
+        dir: PathBuf,
+
+        /// Path to the template within `dir`
+        path: PathBuf,
+    },
     Remote {
         name: String,
         remote: Box<RemoteTemplate>,
@@ -28,7 +34,7 @@ pub enum Template {
 impl Display for Template {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Template::Local(p) => p.to_str().unwrap_or("invalid-file-name").fmt(f),
+            Template::Local { dir: _, path } => path.to_str().unwrap_or("invalid-file-name").fmt(f),
             Template::Remote { name, remote: _ } => write!(
                 f,
                 "{}{} (remote){}",
@@ -40,16 +46,39 @@ impl Display for Template {
     }
 }
 
+impl Template {
+    /// The name the template is selected by with `init --template`
+    pub fn name(&self) -> String {
+        match self {
+            Template::Local { dir: _, path } => path.with_extension("").display().to_string(),
+            Template::Remote { name, remote: _ } => name.clone(),
+        }
+    }
+}
+
+/// All directories containing local templates, in the order they are searched
+pub fn template_dirs(config: &Config) -> Vec<&PathBuf> {
+    config
+        .template_dirs
+        .iter()
+        .chain([&config.templates_dir])
+        .collect()
+}
+
 pub fn get_templates(config: &Config) -> Vec<Template> {
     // Get the local templates
-    let mut templates: Vec<Template> = get_local_templates(&config.templates_dir)
-        .iter()
-        .map(|t| {
-            Template::Local(
-                t.strip_prefix(&config.templates_dir)
-                    .expect("local templates should always be in the template directory")
-                    .to_path_buf(),
-            )
+    let mut templates: Vec<Template> = template_dirs(config)
+        .into_iter()
+        .flat_map(|dir| {
+            get_local_templates(dir)
+                .into_iter()
+                .map(move |t| Template::Local {
+                    dir: dir.clone(),
+                    path: t
+                        .strip_prefix(dir)
+                        .expect("local templates should always be in the template directory")
+                        .to_path_buf(),
+                })
         })
         .collect();
 
@@ -79,7 +108,8 @@ where
 
     for file in utils::read_dir(templates_dir.as_ref()) {
         let path = utils::handle_file_iter(file).path();
-        if path.is_file() {
+        // Other files, like `.tex` file templates, are not document templates
+        if path.is_file() && path.extension() == Some(OsStr::new("zip")) {
             templates.push(
                 templates_dir
                     .as_ref()
@@ -97,8 +127,8 @@ pub fn search_templates<'a>(name: &String, templates: &'a Vec<Template>) -> Opti
     let name_path = PathBuf::from(&name);
     for t in templates {
         match t {
-            Template::Local(p) => {
-                if p == &name_path.with_extension("zip") {
+            Template::Local { dir: _, path } => {
+                if path == &name_path.with_extension("zip") {
                     return Some(t);
                 }
             }
@@ -214,9 +244,31 @@ fn add_path(
     }
 }
 
-pub fn list_templates(config: Config) {
-    for t in get_templates(&config) {
-        println!("{t}")
+pub fn list_templates(config: Config, args: TemplateListArgs) {
+    let templates = get_templates(&config);
+
+    if args.names {
+        let mut names: Vec<String> = vec![];
+        for name in templates.iter().map(|t| t.name()) {
+            if !names.contains(&name) {
+                println!("{name}");
+                names.push(name);
+            }
+        }
+        return;
+    }
+
+    for t in templates {
+        match &t {
+            // Show where templates outside of the personal template directory come from
+            Template::Local { dir, path: _ } if dir != &config.templates_dir => println!(
+                "{t} {}({}){}",
+                Fg(color::LightBlack),
+                dir.display(),
+                Fg(color::Reset)
+            ),
+            _ => println!("{t}"),
+        }
     }
 }
 
@@ -1,8 +1,15 @@
 use serial_test::serial;
 
 use crate::{
-    opts::{Config, Opts, RemoteTemplate},
-    run, utils,
+    config::{self, Severity},
+    deps::{DepGraph, DepKind},
+    log,
+    manifest::BuildManifest,
+    opts::{Config, Opts, PartialConfig, RemoteTemplate},
+    run,
+    synctex::SyncTex,
+    templates::{self, Template},
+    utils,
 };
 use std::{
     fs,
@@ -263,10 +270,9 @@ fn test_remote_template_config() {
             url: "https://github.com/BalderHolst/blatex".to_string(),
             path: Some(PathBuf::from("tests")),
             branch: Some("main".to_string()),
-            config: {
-                let mut config = opts.config.clone();
-                config.main_file = PathBuf::from("main1.tex");
-                config
+            config: PartialConfig {
+                main_file: Some(PathBuf::from("main1.tex")),
+                ..Default::default()
             },
         },
     );
@@ -274,3 +280,760 @@ fn test_remote_template_config() {
     run(opts.clone());
     assert!(opts.config.root.join("main1.pdf").exists())
 }
+
+#[test]
+fn test_config_validation() {
+    let source = r#"
+main_file = 3
+compile_command = "latexmk -pdf <main-file>"
+log_level = "info"
+
+[remote_templates]
+good = "https://github.com/BalderHolst/blatex"
+bad = 4
+missing-repo = { path = "templates" }
+"#;
+    let issues = config::validate(source);
+    let messages: Vec<(Severity, &str)> = issues
+        .iter()
+        .map(|i| (i.severity, i.message.as_str()))
+        .collect();
+
+    assert_eq!(
+        messages,
+        vec![
+            (Severity::Error, "`main_file` must be a string, found integer"),
+            (
+                Severity::Warning,
+                "unknown key `compile_command`, did you mean `compile_cmd`?"
+            ),
+            (
+                Severity::Error,
+                "`log_level` must be one of `errors`, `warnings`, `all`, found `info`"
+            ),
+            (
+                Severity::Error,
+                "remote template `bad` must be a repository url or a table of options, found integer"
+            ),
+            (
+                Severity::Error,
+                "remote template `missing-repo` does not define a `repo`"
+            ),
+        ]
+    );
+
+    assert_eq!(&source[issues[0].span.clone()], "3");
+    assert_eq!(&source[issues[3].span.clone()], "4");
+
+    let issues = config::validate("main_file = 1979-05-27");
+    assert_eq!(
+        issues[0].message,
+        "`main_file` must be a string, found datetime"
+    );
+
+    assert!(config::validate("main_file = \"main.tex\"").is_empty());
+
+    // Remote templates may set fields of any type
+    let source = "[remote_templates.report]\nrepo = \"https://example.com/t\"\ncompile_timeout = 30\ntemplate_dirs = [\"t\"]\n";
+    assert!(config::validate(source).is_empty());
+    let issues = config::validate(
+        "[remote_templates.report]\nrepo = \"https://example.com/t\"\ncompile_timeout = \"30\"\n",
+    );
+    assert_eq!(
+        issues[0].message,
+        "`compile_timeout` must be a integer, found string"
+    );
+}
+
+#[test]
+#[serial]
+fn test_local_config_round_trip() {
+    let (_ctx, mut opts) = setup!("config", "create");
+    opts.config.compile_cmd =
+        r#"latexmk -pdf -pdflatex="pdflatex -interaction=nonstopmode" <main-file>"#.to_string();
+    run(opts.clone());
+
+    let local = PartialConfig::try_from_file(&opts.cwd.join(config::LOCAL_CONFIG_FILE)).unwrap();
+    assert_eq!(local.compile_cmd, Some(opts.config.compile_cmd));
+    assert_eq!(local.main_file, Some(opts.config.main_file));
+    assert!(local.root.is_none());
+}
+
+#[test]
+fn test_full_config_round_trip() {
+    let mut config = Config::default();
+    config.remote_templates.insert(
+        "report".to_string(),
+        RemoteTemplate::new(
+            "https://github.com/BalderHolst/blatex".to_string(),
+            Some(PathBuf::from("templates/basic.zip")),
+            Some("main".to_string()),
+            PartialConfig {
+                compile_cmd: Some("latexmk -pdf \"<main-file>\"".to_string()),
+                ..Default::default()
+            },
+        ),
+    );
+
+    let source = toml::to_string_pretty(&PartialConfig::from(&config)).unwrap();
+
+    // Every serialized field should be known to the validator
+    assert!(config::validate(&source).is_empty(), "{source}");
+
+    let mut parsed = Config::default();
+    parsed.merge(toml::from_str(&source).unwrap());
+    let remote = &parsed.remote_templates["report"];
+    assert_eq!(remote.url, "https://github.com/BalderHolst/blatex");
+    assert_eq!(remote.path, Some(PathBuf::from("templates/basic.zip")));
+    assert_eq!(remote.branch, Some("main".to_string()));
+    assert_eq!(
+        remote.config.compile_cmd,
+        Some("latexmk -pdf \"<main-file>\"".to_string())
+    );
+    assert_eq!(parsed.compile_cmd, config.compile_cmd);
+}
+
+#[test]
+#[serial]
+fn test_config_extends() {
+    let (_ctx, opts) = setup!("compile");
+
+    fs::create_dir(opts.cwd.join("shared")).unwrap();
+    fs::write(
+        opts.cwd.join("shared/base.toml"),
+        "compile_cmd = \"latexmk -pdf <main-file>\"\nclean_cmd = \"latexmk -c\"\n",
+    )
+    .unwrap();
+    fs::write(
+        opts.cwd.join("shared/group.toml"),
+        "extends = [\"base.toml\"]\nclean_cmd = \"latexmk -C\"\n",
+    )
+    .unwrap();
+    fs::write(
+        opts.cwd.join(config::LOCAL_CONFIG_FILE),
+        "extends = [\"shared/group.toml\"]\nmain_file = \"paper.tex\"\n",
+    )
+    .unwrap();
+
+    let mut config = opts.config.clone();
+    for layer in PartialConfig::try_layers(&opts.cwd.join(config::LOCAL_CONFIG_FILE)).unwrap() {
+        config.merge(layer);
+    }
+
+    assert_eq!(config.compile_cmd, "latexmk -pdf <main-file>");
+    assert_eq!(config.clean_cmd, "latexmk -C");
+    assert_eq!(config.main_file, PathBuf::from("paper.tex"));
+}
+
+#[test]
+#[serial]
+fn test_config_extends_paths() {
+    let (_ctx, opts) = setup!("compile");
+
+    let shared = opts.cwd.join("shared");
+    fs::create_dir(&shared).unwrap();
+    fs::write(
+        shared.join("base.toml"),
+        "data_dir = \"data\"\ntemplates_dir = \"templates\"\ntemp_dir = \"tmp\"\nmeta_file = \"meta.tex\"\n",
+    )
+    .unwrap();
+    fs::write(
+        opts.cwd.join(config::LOCAL_CONFIG_FILE),
+        "extends = [\"shared/base.toml\"]\n",
+    )
+    .unwrap();
+
+    // Paths in the extended file are relative to it, wherever blatex runs
+    let mut config = opts.config.clone();
+    for layer in PartialConfig::try_layers(&opts.cwd.join(config::LOCAL_CONFIG_FILE)).unwrap() {
+        config.merge(layer);
+    }
+    assert_eq!(config.data_dir, shared.join("data"));
+    assert_eq!(config.templates_dir, shared.join("templates"));
+    assert_eq!(config.temp_dir, shared.join("tmp"));
+    assert_eq!(config.meta_file, Some(shared.join("meta.tex")));
+
+    fs::write(shared.join("base.toml"), "meta_file = \"\"\n").unwrap();
+    let layers = PartialConfig::try_layers(&opts.cwd.join(config::LOCAL_CONFIG_FILE)).unwrap();
+    assert_eq!(layers[0].meta_file, Some(PathBuf::new()));
+}
+
+#[test]
+#[serial]
+#[should_panic(expected = "cycle")]
+fn test_config_extends_cycle() {
+    let (_ctx, opts) = setup!("compile");
+
+    fs::write(opts.cwd.join("a.toml"), "extends = [\"b.toml\"]\n").unwrap();
+    fs::write(opts.cwd.join("b.toml"), "extends = [\"a.toml\"]\n").unwrap();
+
+    PartialConfig::try_layers(&opts.cwd.join("a.toml")).unwrap();
+}
+
+#[test]
+#[serial]
+fn test_config_try_layers() {
+    let (_ctx, opts) = setup!("compile");
+
+    fs::write(opts.cwd.join("a.toml"), "extends = [\"b.toml\"]\n").unwrap();
+    fs::write(opts.cwd.join("b.toml"), "extends = [\"a.toml\"]\n").unwrap();
+    let error = PartialConfig::try_layers(&opts.cwd.join("a.toml")).unwrap_err();
+    assert!(error.contains("cycle"));
+
+    fs::write(opts.cwd.join("c.toml"), "compile_timeout = \"soon\"\n").unwrap();
+    let error = PartialConfig::try_layers(&opts.cwd.join("c.toml")).unwrap_err();
+    assert!(error.contains("Invalid configuration file"));
+
+    fs::write(
+        opts.cwd.join(config::LOCAL_CONFIG_FILE),
+        "extends = [\"c.toml\"]\n",
+    )
+    .unwrap();
+    let error = Config::try_new_local(&opts.cwd, None).unwrap_err();
+    assert!(error.contains("c.toml"));
+
+    let missing = SyncTex::try_read(&opts.cwd.join("missing.synctex.gz"));
+    assert!(missing.unwrap_err().contains("Could not open"));
+}
+
+#[test]
+#[serial]
+fn test_project_template_dirs() {
+    let (_ctx, mut opts) = setup!("template", "list");
+
+    let project_templates = opts.cwd.join("templates");
+    fs::create_dir(&project_templates).unwrap();
+    fs::copy(
+        "./templates/minimal.zip",
+        project_templates.join("minimal.zip"),
+    )
+    .unwrap();
+    fs::copy(
+        "./templates/slides.zip",
+        project_templates.join("slides.zip"),
+    )
+    .unwrap();
+    fs::copy(
+        "./templates/minimal.zip",
+        opts.config.templates_dir.join("minimal.zip"),
+    )
+    .unwrap();
+
+    opts.config.merge(PartialConfig {
+        template_dirs: Some(vec![project_templates.clone()]),
+        ..Default::default()
+    });
+
+    let templates = templates::get_templates(&opts.config);
+    assert_eq!(templates.len(), 3);
+
+    // Project templates shadow personal ones with the same name
+    match templates::search_templates(&"minimal".to_string(), &templates) {
+        Some(Template::Local { dir, path: _ }) => assert_eq!(dir, &project_templates),
+        t => panic!("unexpected template: {t:?}"),
+    }
+}
+
+#[test]
+#[serial]
+fn test_add_file() {
+    let (_ctx, opts) = setup!("add-file", "chapter", "related_work", "--include");
+    fs::copy("./tests/main1.tex", opts.cwd.join("main.tex")).unwrap();
+    run(opts.clone());
+
+    let chapter = fs::read_to_string(opts.cwd.join("chapters/related_work.tex")).unwrap();
+    assert!(chapter.contains(r"\chapter{Related work}"));
+    assert!(chapter.contains(r"\label{chap:related_work}"));
+
+    let main = fs::read_to_string(opts.cwd.join("main.tex")).unwrap();
+    assert!(main.contains("\\include{chapters/related_work}\n\\end{document}"));
+
+    // Project file templates take precedence over the builtin ones
+    let project_templates = opts.cwd.join("templates");
+    fs::create_dir(&project_templates).unwrap();
+    fs::write(
+        project_templates.join("exercise.tex"),
+        "\\section*{<title>}\n",
+    )
+    .unwrap();
+    let mut config = opts.config.clone();
+    config.template_dirs.push(project_templates);
+    run(Opts::create_mock(
+        vec![
+            "scaffold",
+            "exercise",
+            "week_1",
+            "-o",
+            "exercises/week1.tex",
+        ],
+        config,
+        opts.cwd.clone(),
+    ));
+    assert_eq!(
+        fs::read_to_string(opts.cwd.join("exercises/week1.tex")).unwrap(),
+        "\\section*{Week 1}\n"
+    );
+}
+
+#[test]
+#[serial]
+fn test_dependency_graph() {
+    let (_ctx, opts) = setup!("deps");
+    let root = &opts.cwd;
+
+    fs::create_dir_all(root.join("chapters")).unwrap();
+    fs::create_dir_all(root.join("figures")).unwrap();
+    fs::write(
+        root.join("main.tex"),
+        r"\documentclass{article}
+\usepackage{graphicx,mystyle}
+\graphicspath{{figures/}}
+\begin{document}
+\input{chapters/intro}
+% \input{chapters/commented}
+\include{chapters/missing}
+\bibliography{refs}
+\end{document}",
+    )
+    .unwrap();
+    fs::write(root.join("mystyle.sty"), "").unwrap();
+    fs::write(
+        root.join("chapters/intro.tex"),
+        r"\includegraphics[width=\linewidth]{plot}",
+    )
+    .unwrap();
+    fs::write(root.join("figures/plot.png"), "").unwrap();
+    fs::write(root.join("refs.bib"), "").unwrap();
+
+    let graph = DepGraph::build(root, Path::new("main.tex"), None);
+    let files: Vec<(PathBuf, DepKind)> = graph
+        .files()
+        .into_iter()
+        .map(|d| (d.path, d.kind))
+        .collect();
+
+    assert_eq!(
+        files,
+        vec![
+            (PathBuf::from("main.tex"), DepKind::Tex),
+            (PathBuf::from("mystyle.sty"), DepKind::Style),
+            (PathBuf::from("chapters/intro.tex"), DepKind::Tex),
+            (PathBuf::from("figures/plot.png"), DepKind::Graphic),
+            (PathBuf::from("chapters/missing.tex"), DepKind::Tex),
+            (PathBuf::from("refs.bib"), DepKind::Bibliography),
+        ]
+    );
+    assert!(!graph.exists(Path::new("chapters/missing.tex")));
+}
+
+/// Write a document with an included chapter, a figure and a bibliography
+fn write_archive_document(root: &Path) {
+    fs::create_dir_all(root.join("chapters")).unwrap();
+    fs::write(
+        root.join("main.tex"),
+        r"\documentclass{article}
+\begin{document}
+\input{chapters/intro}
+\includegraphics{plot.png}
+\bibliography{refs}
+\end{document}",
+    )
+    .unwrap();
+    fs::write(root.join("chapters/intro.tex"), "Intro").unwrap();
+    fs::write(root.join("plot.png"), "").unwrap();
+    fs::write(root.join("refs.bib"), "").unwrap();
+    fs::write(root.join("main.bbl"), "").unwrap();
+    fs::write(root.join("unused.tex"), "").unwrap();
+}
+
+#[test]
+#[serial]
+fn test_archive() {
+    let (_ctx, mut opts) = setup!("archive", "-o", "paper.zip", "--verify");
+    write_archive_document(&opts.cwd);
+    opts.config.compile_cmd = "test -f chapters/intro.tex && touch main.pdf".to_string();
+    run(opts.clone());
+
+    let zip = zip::ZipArchive::new(fs::File::open(opts.cwd.join("paper.zip")).unwrap()).unwrap();
+    let mut names: Vec<&str> = zip.file_names().collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "chapters/intro.tex",
+            "main.bbl",
+            "main.tex",
+            "plot.png",
+            "refs.bib"
+        ]
+    );
+
+    let tar_opts = Opts::create_mock(
+        vec!["archive", "--format", "tar-gz"],
+        opts.config.clone(),
+        opts.cwd.clone(),
+    );
+    run(tar_opts);
+    assert!(opts.cwd.join("main.tar.gz").is_file());
+}
+
+#[test]
+#[serial]
+#[should_panic(expected = "does not compile on its own")]
+fn test_archive_verify_missing_file() {
+    let (_ctx, mut opts) = setup!("archive", "--verify");
+    write_archive_document(&opts.cwd);
+    opts.config.compile_cmd = "test -f unused.tex && touch main.pdf".to_string();
+    run(opts);
+}
+
+#[test]
+#[serial]
+#[should_panic(expected = "outside of the document root: `../shared/macros.tex`")]
+fn test_archive_outside_root() {
+    let (_ctx, opts) = setup!("archive");
+    write_archive_document(&opts.cwd);
+    let shared = opts.cwd.parent().unwrap().join("shared");
+    fs::create_dir_all(&shared).unwrap();
+    fs::write(shared.join("macros.tex"), "").unwrap();
+    fs::write(
+        opts.cwd.join("chapters/intro.tex"),
+        "\\input{../shared/macros}\nIntro",
+    )
+    .unwrap();
+    run(opts);
+}
+
+#[test]
+#[serial]
+fn test_arxiv() {
+    let (_ctx, opts) = setup!("arxiv", "--flatten");
+    let root = &opts.cwd;
+    fs::create_dir_all(root.join("chapters")).unwrap();
+    fs::create_dir_all(root.join("figures")).unwrap();
+    fs::write(
+        root.join("main.tex"),
+        r"\documentclass{article}
+% Notes for coauthors
+\begin{document}
+\input{chapters/intro} % TODO
+\bibliography{refs}
+\end{document}
+",
+    )
+    .unwrap();
+    fs::write(
+        root.join("chapters/intro.tex"),
+        r"\includegraphics{figures/my plot}",
+    )
+    .unwrap();
+    fs::write(root.join("figures/my plot.png"), "").unwrap();
+    fs::write(root.join("refs.bib"), "").unwrap();
+    fs::write(
+        root.join("main.bbl"),
+        "\\begin{thebibliography}{1}\n\\end{thebibliography}\n",
+    )
+    .unwrap();
+    run(opts.clone());
+
+    let tarball = fs::File::open(root.join("main-arxiv.tar.gz")).unwrap();
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(tarball));
+    let mut files: Vec<(String, String)> = archive
+        .entries()
+        .unwrap()
+        .map(|e| {
+            let mut e = e.unwrap();
+            let name = e.path().unwrap().display().to_string();
+            let mut contents = String::new();
+            std::io::Read::read_to_string(&mut e, &mut contents).unwrap();
+            (name, contents)
+        })
+        .collect();
+    files.sort();
+
+    let names: Vec<&str> = files.iter().map(|(n, _)| n.as_str()).collect();
+    assert_eq!(names, vec!["intro.tex", "main.tex", "my_plot.png"]);
+    assert_eq!(files[0].1, r"\includegraphics{my_plot}");
+    assert_eq!(
+        files[1].1,
+        r"\documentclass{article}
+\begin{document}
+\input{intro} %
+\begin{thebibliography}{1}
+\end{thebibliography}
+\end{document}
+"
+    );
+}
+
+/// Write a document with `\include`d chapters, a subfile and figures found through
+/// `\graphicspath`
+fn write_flatten_document(root: &Path) {
+    fs::create_dir_all(root.join("chapters")).unwrap();
+    fs::create_dir_all(root.join("figures")).unwrap();
+    fs::write(
+        root.join("main.tex"),
+        r"\documentclass{article}
+\usepackage{graphicx,subfiles}
+\graphicspath{{figures/}}
+\includeonly{chapters/intro}
+\begin{document}
+\include{chapters/intro}
+\include{chapters/method} % Not finished
+\subfile{chapters/appendix}
+\bibliography{refs}
+\end{document}
+",
+    )
+    .unwrap();
+    fs::write(
+        root.join("chapters/intro.tex"),
+        "Intro \\input{chapters/details}\n% \\input{chapters/missing}\n",
+    )
+    .unwrap();
+    fs::write(root.join("chapters/details.tex"), "\\includegraphics{plot}").unwrap();
+    fs::write(root.join("chapters/method.tex"), "Method").unwrap();
+    fs::write(
+        root.join("chapters/appendix.tex"),
+        "\\documentclass[../main]{subfiles}\n\\begin{document}\nAppendix\n\\end{document}\n",
+    )
+    .unwrap();
+    fs::write(root.join("figures/plot.png"), "").unwrap();
+    fs::write(
+        root.join("main.bbl"),
+        "\\begin{thebibliography}{1}\n\\end{thebibliography}\n",
+    )
+    .unwrap();
+}
+
+#[test]
+#[serial]
+fn test_flatten() {
+    let (_ctx, opts) = setup!("flatten", "--bbl");
+    write_flatten_document(&opts.cwd);
+    run(opts.clone());
+
+    assert_eq!(
+        fs::read_to_string(opts.cwd.join("main-flat.tex")).unwrap(),
+        r"\documentclass{article}
+\usepackage{graphicx,subfiles}
+\graphicspath{{figures/}}
+
+\begin{document}
+\clearpage%
+%% >>> chapters/intro.tex:1
+Intro %
+%% >>> chapters/details.tex:1
+\includegraphics{plot}
+%% <<< chapters/details.tex, back in chapters/intro.tex:1
+% \input{chapters/missing}
+%% <<< chapters/intro.tex, back in main.tex:6
+\clearpage
+\clearpage % Not finished
+%
+%% >>> chapters/appendix.tex:3
+Appendix
+%% <<< chapters/appendix.tex, back in main.tex:8
+%
+%% >>> bibliography
+\begin{thebibliography}{1}
+\end{thebibliography}
+%% <<< bibliography, back in main.tex:9
+\end{document}
+"
+    );
+}
+
+#[test]
+#[serial]
+fn test_flatten_into_directory() {
+    let (_ctx, opts) = setup!("flatten", "-o", "submission/paper.tex");
+    write_flatten_document(&opts.cwd);
+    fs::write(
+        opts.cwd.join("chapters/details.tex"),
+        "\\includegraphics{plot}\n\\input{chapters/missing}\n\\begin{verbatim}\n\\input{chapters/method}\n\\end{verbatim}\n",
+    )
+    .unwrap();
+    run(opts.clone());
+
+    let flat = fs::read_to_string(opts.cwd.join("submission/paper.tex")).unwrap();
+    assert!(flat.contains(r"\graphicspath{{../figures/}}"));
+    assert!(flat.contains(r"\includegraphics{../figures/plot.png}"));
+    assert!(flat.contains(r"\bibliography{../refs}"));
+    assert!(flat.contains(r"\input{../chapters/missing}"));
+    assert!(flat.contains("\\begin{verbatim}\n\\input{chapters/method}\n\\end{verbatim}\n"));
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .args([
+            "-c",
+            "user.name=blatex",
+            "-c",
+            "user.email=blatex@example.com",
+        ])
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+#[serial]
+fn test_diff() {
+    let (_ctx, mut opts) = setup!("diff", "HEAD~1", "HEAD", "--differ", "words");
+    let root = opts.cwd.clone();
+    fs::create_dir_all(root.join("chapters")).unwrap();
+    fs::write(
+        root.join("main.tex"),
+        "\\documentclass{article}\n\\begin{document}\n\\input{chapters/intro}\n\\end{document}\n",
+    )
+    .unwrap();
+    fs::write(root.join("chapters/intro.tex"), "A short text.\n").unwrap();
+    git(&root, &["init", "-q"]);
+    git(&root, &["add", "-A"]);
+    git(&root, &["commit", "-qm", "First"]);
+    fs::write(root.join("chapters/intro.tex"), "A longer text.\n").unwrap();
+    git(&root, &["commit", "-qam", "Second"]);
+
+    // The compiled document is the marked up source
+    opts.config.compile_cmd = "cp <main-file> diff.pdf && touch diff.log".to_string();
+    run(opts.clone());
+
+    let marked = fs::read_to_string(root.join("diff.pdf")).unwrap();
+    assert!(marked.contains("A \\DIFdel{short} \\DIFadd{longer} text."));
+    assert!(opts
+        .config
+        .temp_dir
+        .join("diff/old/chapters/intro.tex")
+        .is_file());
+}
+
+#[test]
+#[serial]
+fn test_meta_file() {
+    let (_ctx, mut opts) = setup!("compile");
+    let root = opts.cwd.clone();
+    fs::write(root.join("main.tex"), "\\input{blatex-meta}\n").unwrap();
+    git(&root, &["init", "-q"]);
+    git(&root, &["add", "-A"]);
+    git(&root, &["commit", "-qm", "First"]);
+
+    opts.config.compile_cmd = "echo <git-rev> > rev.txt && touch main.log main.pdf".to_string();
+    run(opts.clone());
+
+    let rev = fs::read_to_string(root.join("rev.txt")).unwrap();
+    let meta = fs::read_to_string(root.join("blatex-meta.tex")).unwrap();
+    assert!(meta.contains(&format!("\\def\\blatexGitRev{{{}}}\n", rev.trim())));
+    assert!(meta.contains("\\def\\blatexTarget{main}\n"));
+    assert!(meta.ends_with("\\blatexGitDirtyfalse\n"));
+
+    // The file is written into the build directory, which TeX searches
+    fs::remove_file(root.join("blatex-meta.tex")).unwrap();
+    opts.config.compile_cmd =
+        ": -output-directory=build && echo \"$TEXINPUTS\" > texinputs.txt && touch build/main.log build/main.pdf"
+            .to_string();
+    run(opts.clone());
+    assert!(root.join("build/blatex-meta.tex").is_file());
+    let texinputs = fs::read_to_string(root.join("texinputs.txt")).unwrap();
+    assert!(texinputs.starts_with(&format!("{}:", root.join("build").display())));
+
+    // An empty path turns the file off
+    fs::remove_dir_all(root.join("build")).unwrap();
+    fs::create_dir(root.join("build")).unwrap();
+    opts.config.meta_file = Some(PathBuf::new());
+    run(opts);
+    assert!(!root.join("build/blatex-meta.tex").exists());
+}
+
+#[test]
+#[serial]
+fn test_build_manifest() {
+    let (_ctx, mut opts) = setup!("compile");
+    let root = &opts.cwd;
+    let main_file = Path::new("main.tex");
+    let cmd = "pdflatex main.tex";
+    opts.config.compile_cmd = "latexmk -outdir=build <main-file>".to_string();
+
+    fs::write(root.join("main.tex"), r"\input{intro}").unwrap();
+    fs::write(root.join("intro.tex"), "Hello").unwrap();
+    fs::write(root.join("macros.sty"), "").unwrap();
+
+    // The compiler puts the PDF and the recorder file in the output directory
+    fs::create_dir(root.join("build")).unwrap();
+    fs::write(
+        root.join("build/main.fls"),
+        format!("PWD {}\nINPUT ./macros.sty\n", root.display()),
+    )
+    .unwrap();
+
+    let config = &opts.config;
+    BuildManifest::compute(config, main_file, cmd).write(config, main_file);
+    assert!(root.join("build/main.build-manifest").is_file());
+    assert!(!root.join("main.build-manifest").exists());
+    let manifest = BuildManifest::read(config, main_file).unwrap();
+    assert!(manifest.inputs.contains_key(Path::new("macros.sty")));
+    assert!(!manifest.is_up_to_date(config, main_file, cmd));
+
+    fs::write(root.join("build/main.pdf"), "").unwrap();
+    assert!(manifest.is_up_to_date(config, main_file, cmd));
+    assert!(!manifest.is_up_to_date(config, main_file, "lualatex main.tex"));
+
+    fs::write(root.join("macros.sty"), "% changed").unwrap();
+    assert!(!manifest.is_up_to_date(config, main_file, cmd));
+    fs::write(root.join("macros.sty"), "").unwrap();
+
+    fs::write(root.join("intro.tex"), "Hello, world").unwrap();
+    assert!(!manifest.is_up_to_date(config, main_file, cmd));
+}
+
+#[test]
+#[serial]
+fn test_find_log_file() {
+    let (_ctx, mut opts) = setup!("log");
+    let root = opts.cwd.clone();
+    let main_file = Path::new("main.tex");
+
+    // The log is found in the output directory of the compilation command
+    fs::create_dir(root.join("build")).unwrap();
+    fs::write(root.join("build/main.log"), "").unwrap();
+    opts.config.compile_cmd = "pdflatex -output-directory=build <main-file>".to_string();
+    assert_eq!(
+        log::find_log_file(&opts.config, main_file),
+        root.join("build/main.log")
+    );
+
+    // Otherwise the newest log with the right job name is used
+    fs::create_dir_all(root.join("out/aux")).unwrap();
+    fs::create_dir(root.join("old")).unwrap();
+    fs::write(root.join("old/thesis.log"), "").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    fs::write(root.join("out/aux/thesis.log"), "").unwrap();
+    opts.config.compile_cmd = "latexmk -pdf -jobname=thesis <main-file>".to_string();
+    assert_eq!(
+        log::find_log_file(&opts.config, main_file),
+        root.join("out/aux/thesis.log")
+    );
+}
+
+#[test]
+#[serial]
+#[should_panic(expected = "ran for more than 1 second\u{1b}[39m")]
+fn test_compile_timeout() {
+    let (_ctx, mut opts) = setup!("compile");
+    opts.config.compile_cmd = "sleep 10".to_string();
+    opts.config.compile_timeout = Some(1);
+    run(opts);
+}
+
+#[test]
+#[serial]
+#[should_panic(expected = "waiting for input")]
+fn test_compile_waiting_for_input() {
+    let (_ctx, mut opts) = setup!("compile");
+    opts.config.compile_cmd = r#"printf "\n? "; sleep 10"#.to_string();
+    run(opts);
+}
@@ -27,7 +27,9 @@ use fuzzy_finder::item::Item;
 
 use crate::utils;
 
-pub fn replace_path_placeholders(s: &str, main_file: &Path) -> String {
+/// Replace `<main-file>` and `<main-stem>` with the main file, and the git placeholders with the
+/// revision of the repository containing `root`
+pub fn replace_path_placeholders(s: &str, main_file: &Path, root: &Path) -> String {
     let main_file_string = main_file.to_str().unwrap();
     let out = replace_text(s, "<main-file>", main_file_string);
 
@@ -36,7 +38,7 @@ pub fn replace_path_placeholders(s: &str, main_file: &Path) -> String {
         .unwrap_or(main_file_string);
     let out = replace_text(out.as_str(), "<main-stem>", stem);
 
-    out
+    crate::meta::replace_git_placeholders(&out, root)
 }
 
 #[test]
@@ -44,13 +46,55 @@ fn test_replace_path_placeholders() {
     assert_eq!(
         replace_path_placeholders(
             "hello <main-file> is the main file. The stem is <main-stem>.",
-            Path::new("mainfile.tex")
+            Path::new("mainfile.tex"),
+            Path::new(".")
         ),
         "hello mainfile.tex is the main file. The stem is mainfile."
     );
 }
 
-fn replace_text(s: &str, pattern: &str, value: &str) -> String {
+/// 64-bit FNV-1a hash. Unlike the hashers in the standard library, it is stable across builds.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[test]
+fn test_hash_bytes() {
+    assert_eq!(hash_bytes(b""), 0xcbf29ce484222325);
+    assert_eq!(hash_bytes(b"a"), 0xaf63dc4c8601ec8c);
+}
+
+/// Levenshtein distance between two strings
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(cur).min(row[j])
+            };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("compile_command", "compile_cmd"), 4);
+    assert_eq!(edit_distance("main-file", "main_file"), 1);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("clean_cmd", "clean_cmd"), 0);
+}
+
+pub fn replace_text(s: &str, pattern: &str, value: &str) -> String {
     let parts = s.split(pattern);
     parts
         .clone()
@@ -59,6 +103,95 @@ fn replace_text(s: &str, pattern: &str, value: &str) -> String {
         .collect::<String>()
 }
 
+/// Resolve a path written in a configuration file. `~` is expanded to the home directory and
+/// relative paths are relative to the directory of the configuration file.
+pub fn resolve_config_path(config_file: &Path, path: &Path) -> PathBuf {
+    let path = match path.strip_prefix("~") {
+        Ok(rest) => match directories::BaseDirs::new() {
+            Some(dirs) => dirs.home_dir().join(rest),
+            None => exit_with_error!("Could not determine home directory."),
+        },
+        Err(_) => path.to_path_buf(),
+    };
+    match path.is_relative() {
+        true => parrent(config_file).join(path),
+        false => path,
+    }
+}
+
+#[test]
+fn test_resolve_config_path() {
+    let config_file = Path::new("/doc/.blatex.toml");
+    assert_eq!(
+        resolve_config_path(config_file, Path::new("../base.toml")),
+        PathBuf::from("/doc/../base.toml")
+    );
+    assert_eq!(
+        resolve_config_path(config_file, Path::new("/etc/blatex.toml")),
+        PathBuf::from("/etc/blatex.toml")
+    );
+    assert!(resolve_config_path(config_file, Path::new("~/base.toml")).is_absolute());
+}
+
+/// Format a cycle of files as `a -> b -> a`
+pub fn format_cycle(files: &[PathBuf], repeated: &Path) -> String {
+    files
+        .iter()
+        .map(|f| f.as_path())
+        .chain([repeated])
+        .map(|f| format!("`{}`", f.display()))
+        .collect::<Vec<String>>()
+        .join(" -> ")
+}
+
+/// Split a shell command into words, respecting single and double quotes
+pub fn split_command(cmd: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+
+    for c in cmd.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+#[test]
+fn test_split_command() {
+    assert_eq!(
+        split_command(r#"latexmk -pdf -outdir="build dir" '' main.tex"#),
+        vec!["latexmk", "-pdf", "-outdir=build dir", "", "main.tex"]
+    );
+}
+
+/// Find an executable in the directories of the `PATH` environment variable
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .flat_map(|dir| [dir.join(name), dir.join(name).with_extension("exe")])
+        .find(|p| p.is_file())
+}
+
+/// Run commands in their own process group, such that the whole tree of processes can be stopped
+#[cfg(unix)]
+pub fn new_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    command.process_group(0);
+}
+
+#[cfg(not(unix))]
+pub fn new_process_group(_command: &mut Command) {}
+
 /// Clones a repository and returns path to the root of the cloned directory.
 pub fn clone_repo(tmp_dir: &Path, url: &str, branch: Option<&String>) -> PathBuf {
     // Path to a temporary directory for cloning repos into.
@@ -0,0 +1,179 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use termion::color::{self, Fg};
+
+use crate::{
+    exit_with_error, log,
+    opts::{Config, ViewArgs},
+    utils,
+};
+
+/// Viewers tried in order when no viewer is configured
+const DEFAULT_VIEWERS: &[&str] = &["zathura", "evince", "okular", "xdg-open", "open"];
+
+/// Viewers which reload the document themselves when it changes
+const AUTO_RELOADING_VIEWERS: &[&str] = &["zathura", "evince", "okular", "qpdfview"];
+
+/// Viewers which reload the document when they receive `SIGHUP`
+const SIGHUP_VIEWERS: &[&str] = &["mupdf", "mupdf-x11", "mupdf-gl"];
+
+/// Programs which hand the document to another program and exit
+const LAUNCHERS: &[&str] = &["xdg-open", "open"];
+
+/// The viewer command with the PDF file substituted for `<pdf>`. The file is added as the last
+/// argument if there is no placeholder.
+fn viewer_command(viewer: &str, pdf: &Path) -> String {
+    let pdf = format!("\"{}\"", pdf.display());
+    match viewer.contains("<pdf>") {
+        true => utils::replace_text(viewer, "<pdf>", &pdf),
+        false => format!("{viewer} {pdf}"),
+    }
+}
+
+/// Name of the program run by a viewer command
+fn viewer_program(viewer: &str) -> String {
+    utils::split_command(viewer)
+        .into_iter()
+        .next()
+        .and_then(|p| {
+            Path::new(&p)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+        })
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_viewer_command() {
+    let pdf = Path::new("/doc/main.pdf");
+    assert_eq!(viewer_command("zathura", pdf), "zathura \"/doc/main.pdf\"");
+    assert_eq!(
+        viewer_command("okular --unique <pdf>#page=1", pdf),
+        "okular --unique \"/doc/main.pdf\"#page=1"
+    );
+    assert_eq!(viewer_program("/usr/bin/mupdf -r 96 <pdf>"), "mupdf");
+}
+
+/// The configured viewer, or the first default viewer installed
+fn find_viewer(config: &Config) -> String {
+    if let Some(viewer) = &config.viewer {
+        return viewer.clone();
+    }
+    match DEFAULT_VIEWERS
+        .iter()
+        .find(|v| utils::find_executable(v).is_some())
+    {
+        Some(v) => v.to_string(),
+        None => exit_with_error!(
+            "Could not find a PDF viewer. Set one with the `viewer` configuration option."
+        ),
+    }
+}
+
+/// Whether the process `pid` is running the viewer `program`. The program name is checked, as the
+/// process id may have been reused since the viewer exited.
+fn is_running(pid: u32, program: &str) -> bool {
+    let output = Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "comm="])
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(o) if o.status.success() => is_program(&String::from_utf8_lossy(&o.stdout), program),
+        _ => false,
+    }
+}
+
+/// Whether the command name reported by `ps` is `program`. Linux cuts command names to 15 bytes,
+/// and macOS gives the full path.
+fn is_program(comm: &str, program: &str) -> bool {
+    let comm = comm.trim();
+    let name = Path::new(comm)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    !name.is_empty() && (name == program || (name.len() == 15 && program.starts_with(&name)))
+}
+
+#[test]
+fn test_is_program() {
+    assert!(is_program("zathura\n", "zathura"));
+    assert!(is_program("/usr/bin/zathura\n", "zathura"));
+    assert!(is_program("a-very-long-vie", "a-very-long-viewer"));
+    assert!(!is_program("bash\n", "zathura"));
+    assert!(!is_program("", "zathura"));
+}
+
+/// Open the PDF of `main_file` in the viewer. If a viewer was already opened for the document,
+/// it is told to reload the document instead. Launchers like `xdg-open` exit right away, so the
+/// viewer they started cannot be found. They are only run again when `reopen` is set.
+pub fn open(config: &Config, main_file: &Path, reopen: bool) {
+    let pdf = log::find_pdf_file(config, main_file);
+    if !pdf.is_file() {
+        exit_with_error!(
+            "Cannot find `{}`. Compile the document first.",
+            pdf.display()
+        );
+    }
+
+    let viewer = find_viewer(config);
+    let program = viewer_program(&viewer);
+    let pid_file = log::aux_file(config, main_file, "viewer-pid");
+    let pid: Option<u32> = fs::read_to_string(&pid_file)
+        .ok()
+        .and_then(|s| s.trim().parse().ok());
+
+    match pid {
+        Some(pid) if is_running(pid, &program) => {
+            if SIGHUP_VIEWERS.contains(&program.as_str()) {
+                let _ = Command::new("kill")
+                    .args(["-HUP", &pid.to_string()])
+                    .status();
+            } else if !AUTO_RELOADING_VIEWERS.contains(&program.as_str()) {
+                println!(
+                    "The viewer is already open. Reload the document in the viewer to see the changes."
+                );
+            }
+            return;
+        }
+        Some(_) if LAUNCHERS.contains(&program.as_str()) && !reopen => return,
+        _ => {}
+    }
+
+    // `exec` makes the viewer replace the shell, such that its process id is known
+    let cmd = format!("exec {}", viewer_command(&viewer, &pdf));
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(&cmd)
+        .current_dir(&config.root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    utils::new_process_group(&mut command);
+
+    let child = match command.spawn() {
+        Ok(c) => c,
+        Err(e) => exit_with_error!("Could not run viewer `{}`: {}", viewer, e),
+    };
+    utils::write(&pid_file, child.id().to_string());
+
+    println!(
+        "{}Opened `{}` in {}.{}",
+        Fg(color::Blue),
+        pdf.strip_prefix(&config.root).unwrap_or(&pdf).display(),
+        program,
+        Fg(color::Reset)
+    );
+}
+
+pub fn view(config: Config, args: ViewArgs) {
+    let main_file = match &args.main_file {
+        Some(f) => PathBuf::from(f),
+        None => config.main_file.clone(),
+    };
+    open(&config, &main_file, true);
+}